@@ -1,10 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 use std::hint::black_box;
 
-use classifier::{tokenize, NaiveBayesClassifier};
+use classifier::tokenizer::tokenize;
+use classifier::NaiveBayesClassifier;
 
 #[allow(dead_code)]
-#[path = "../src/classifier.rs"]
+#[path = "../src/classifier/mod.rs"]
 mod classifier;
 
 fn bench_classify_short_text(c: &mut Criterion) {
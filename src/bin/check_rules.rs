@@ -0,0 +1,101 @@
+//! Check an operator rules file before deploying it
+//!
+//! The rules are compiled as the component would, and optionally run against
+//! sample messages listing the rules they should hit. The process exits with
+//! an error status when a rule is invalid or a sample doesn't hit the rules it
+//! expects.
+
+use std::fs::File;
+use std::io::{self, BufRead};
+
+use classifier::email::Email;
+use classifier::rules::{RuleAction, RuleSet};
+use classifier::Document;
+
+#[allow(dead_code)]
+#[path = "../classifier/mod.rs"]
+mod classifier;
+
+/// Sample message, with the fields of an email and the names of the rules it should hit
+#[derive(serde::Deserialize)]
+struct Sample {
+    #[serde(flatten)]
+    email: Email,
+    #[serde(default)]
+    expect: Vec<String>,
+}
+
+fn main() {
+    let rules_path = std::env::args()
+        .nth(1)
+        .expect("Should have rules file as first argument");
+    let source = std::fs::read_to_string(&rules_path).expect("Could not read rules file");
+
+    let rules = match RuleSet::parse(&source) {
+        Ok(rules) => rules,
+        Err(err) => {
+            println!("ERROR: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    println!("Compiled {} rules:", rules.rules().len());
+    for rule in rules.rules() {
+        match rule.action {
+            RuleAction::Score(score) => println!("  {}: score {score:+}", rule.name),
+            RuleAction::Verdict(verdict) => println!(
+                "  {}: verdict {}",
+                rule.name,
+                serde_json::to_string(&verdict).unwrap()
+            ),
+        }
+    }
+
+    let Some(samples_path) = std::env::args().nth(2) else {
+        return;
+    };
+    let file = File::open(&samples_path).expect("Could not open samples file");
+
+    let mut samples = 0;
+    let mut failures = 0;
+    for (index, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line.expect("Could not read samples file");
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        samples += 1;
+        let sample: Sample = match serde_json::from_str(&line) {
+            Ok(sample) => sample,
+            Err(err) => {
+                println!("FAIL line {}: invalid sample: {err}", index + 1);
+                failures += 1;
+                continue;
+            }
+        };
+
+        let mut hits: Vec<_> = rules
+            .evaluate(&sample.email.visible_text())
+            .into_iter()
+            .map(|hit| hit.rule)
+            .collect();
+        let mut expected = sample.expect;
+        hits.sort();
+        expected.sort();
+
+        if hits != expected {
+            println!(
+                "FAIL line {}: expected {:?}, hit {:?}",
+                index + 1,
+                expected,
+                hits
+            );
+            failures += 1;
+        }
+    }
+
+    println!("Checked {samples} samples, {failures} failed");
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
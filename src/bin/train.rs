@@ -1,24 +1,49 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use fst::Streamer;
 
+use classifier::calibration::{Calibration, CalibrationMethod};
+use classifier::domains::{self, DomainLists};
+use classifier::email::Email;
+use classifier::model::{self, LabelStats, ModelFile, ModelHeader, ModelSections};
+use classifier::multiclass::LabelTable;
+use classifier::{Document, LABEL_HAM, LABEL_SPAM};
+
 #[allow(dead_code)]
-#[path = "../classifier.rs"]
+#[path = "../classifier/mod.rs"]
 mod classifier;
 
 const TEXT_INDEX: usize = 0;
 const LABEL_INDEX: usize = 1;
 
-const LABEL_HAM: &str = "ham";
-const LABEL_SPAM: &str = "spam";
+/// Labeled email of a JSONL dataset, the body may also be given as `text`
+#[derive(serde::Deserialize)]
+struct EmailRecord {
+    #[serde(flatten)]
+    email: Email,
+    label: String,
+}
+
+/// Labels standing for legitimate messages, by order of preference
+const HAM_LABELS: &[&str] = &[LABEL_HAM, "legitimate"];
+
+/// Share of the dataset held out to fit the calibration map, by default
+const DEFAULT_HOLDOUT: f64 = 0.2;
 
 #[derive(Debug)]
 struct TrainingStats {
     total_samples: u32,
     spam_samples: u32,
     ham_samples: u32,
+    label_samples: BTreeMap<String, u32>,
+    held_out_samples: u32,
+    /// Samples tokenized and added to the model, without the held-out ones
+    trained_samples: u32,
+    /// Tokens of the trained samples
+    trained_tokens: u32,
     total_tokens: u32,
     unique_tokens: u32,
     avg_tokens_per_sample: f32,
@@ -30,6 +55,10 @@ impl TrainingStats {
             total_samples: 0,
             spam_samples: 0,
             ham_samples: 0,
+            label_samples: BTreeMap::new(),
+            held_out_samples: 0,
+            trained_samples: 0,
+            trained_tokens: 0,
             total_tokens: 0,
             unique_tokens: 0,
             avg_tokens_per_sample: 0.0,
@@ -49,6 +78,21 @@ impl TrainingStats {
             self.ham_samples,
             (self.ham_samples as f32 / self.total_samples as f32) * 100.0
         );
+        for (label, samples) in &self.label_samples {
+            println!(
+                "  {}: {} ({:.1}%)",
+                label,
+                samples,
+                (*samples as f32 / self.total_samples as f32) * 100.0
+            );
+        }
+        if self.held_out_samples > 0 {
+            println!(
+                "Held-out samples: {} ({:.1}%)",
+                self.held_out_samples,
+                (self.held_out_samples as f32 / self.total_samples as f32) * 100.0
+            );
+        }
         println!("Total tokens: {}", self.total_tokens);
         println!("Unique tokens: {}", self.unique_tokens);
         println!(
@@ -59,6 +103,242 @@ impl TrainingStats {
     }
 }
 
+/// Get the column of a label, adding it if it's the first time it's seen
+fn label_index(labels: &mut Vec<LabelStats>, name: &str) -> usize {
+    if let Some(index) = labels.iter().position(|label| label.name == name) {
+        return index;
+    }
+
+    labels.push(LabelStats {
+        name: name.to_string(),
+        ..Default::default()
+    });
+    labels.len() - 1
+}
+
+/// Get the value following a `--name` flag, after the positional arguments
+fn option(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(3);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Whether a document belongs to the held-out split
+///
+/// Documents are split on a hash of their subject and body, so that retraining
+/// on the same dataset holds out the same documents.
+fn is_held_out(email: &Email, fraction: f64) -> bool {
+    // FNV-1a
+    let hash = email
+        .subject
+        .bytes()
+        .chain(email.body.bytes())
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    (hash % 10_000) as f64 / 10_000.0 < fraction
+}
+
+/// Token counts of a model being trained
+#[derive(Default)]
+struct ModelBuilder {
+    // Per-token counts, one column per label
+    counters: HashMap<String, Vec<u32>>,
+    labels: Vec<LabelStats>,
+    // Documents left out of the counts to fit the calibration map, with their label
+    held_out: Vec<(Email, String)>,
+}
+
+impl ModelBuilder {
+    /// Load the counts of an existing model to extend it
+    fn load(file: &ModelFile) -> Self {
+        let header = file.header.clone().unwrap_or_else(|| {
+            println!("Existing model has no header, document counts will only cover this dataset");
+            ModelHeader::default()
+        });
+
+        let (labels, table) = if header.labels.is_empty() {
+            // Binary model with packed spam/ham counters
+            let labels = vec![
+                LabelStats {
+                    name: LABEL_SPAM.to_string(),
+                    documents: header.spam_documents,
+                    tokens: 0,
+                },
+                LabelStats {
+                    name: LABEL_HAM.to_string(),
+                    documents: header.ham_documents,
+                    tokens: 0,
+                },
+            ];
+            (labels, None)
+        } else {
+            let table = LabelTable::new(header.labels.clone(), &header.ham_label, file.counts);
+            (header.labels, Some(table))
+        };
+
+        let map = fst::Map::new(file.fst).unwrap();
+        let mut counters = HashMap::with_capacity(map.len());
+
+        let mut stream = map.stream();
+        while let Some((key, value)) = stream.next() {
+            let key = String::from_utf8(key.to_vec()).unwrap();
+            let row = match &table {
+                Some(table) => (0..labels.len())
+                    .map(|label| table.count(value, label))
+                    .collect(),
+                None => {
+                    let counter = classifier::Counter::from_u64(value);
+                    vec![counter.spam, counter.ham]
+                }
+            };
+
+            counters.insert(key, row);
+        }
+
+        Self {
+            counters,
+            labels,
+            held_out: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    fn total_tokens(&self) -> u32 {
+        self.counters.values().flatten().sum()
+    }
+
+    fn add_document(&mut self, label: &str, tokens: &[String]) {
+        let index = label_index(&mut self.labels, label);
+        self.labels[index].documents += 1;
+
+        for token in tokens {
+            let row = self.counters.entry(token.clone()).or_default();
+            if row.len() <= index {
+                row.resize(index + 1, 0);
+            }
+            row[index] += 1;
+        }
+    }
+
+    fn hold_out(&mut self, label: &str, email: &Email) {
+        self.held_out.push((email.clone(), label.to_string()));
+    }
+
+    fn ham_label(&self) -> Option<&'static str> {
+        HAM_LABELS
+            .iter()
+            .find(|ham_label| self.labels.iter().any(|label| label.name == **ham_label))
+            .copied()
+    }
+
+    /// Build the FST, mapping each token to its row in the counts table, along with the header
+    ///
+    /// With a calibration method, the calibration map is fitted on the held-out documents.
+    fn build(
+        self,
+        tokenizer: &classifier::TokenizerConfig,
+        calibration: Option<CalibrationMethod>,
+    ) -> (ModelHeader, Vec<u32>, Vec<u8>) {
+        let ham_label = self.ham_label().unwrap_or(LABEL_HAM);
+        let ModelBuilder {
+            counters,
+            mut labels,
+            held_out,
+        } = self;
+        let vocabulary_size = counters.len() as u32;
+
+        let mut counters: Vec<_> = counters.into_iter().collect();
+        counters.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+        let mut builder = fst::MapBuilder::memory();
+        let mut counts = Vec::with_capacity(counters.len() * labels.len());
+
+        for label in labels.iter_mut() {
+            label.tokens = 0;
+        }
+
+        for (row, (word, mut counter)) in counters.into_iter().enumerate() {
+            counter.resize(labels.len(), 0);
+            for (label, count) in labels.iter_mut().zip(&counter) {
+                label.tokens += count;
+            }
+
+            counts.extend(counter);
+            builder.insert(word, row as u64).unwrap();
+        }
+
+        let fst = builder.into_inner().unwrap();
+
+        let (mut spam_label_stats, mut ham_label_stats) =
+            (LabelStats::default(), LabelStats::default());
+        for label in &labels {
+            let pooled = if label.name == ham_label {
+                &mut ham_label_stats
+            } else {
+                &mut spam_label_stats
+            };
+            pooled.documents += label.documents;
+            pooled.tokens += label.tokens;
+        }
+
+        let header = ModelHeader {
+            spam_documents: spam_label_stats.documents,
+            ham_documents: ham_label_stats.documents,
+            spam_tokens: spam_label_stats.tokens,
+            ham_tokens: ham_label_stats.tokens,
+            vocabulary_size,
+            tokenizer: tokenizer.clone(),
+            tokenizer_fingerprint: Some(tokenizer.fingerprint()),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            labels,
+            ham_label: ham_label.to_string(),
+            ..Default::default()
+        };
+
+        let Some(method) = calibration else {
+            return (header, counts, fst);
+        };
+        if held_out.is_empty() {
+            println!("WARNING: no held-out documents, the model won't be calibrated");
+            return (header, counts, fst);
+        }
+
+        // Score the held-out documents with the uncalibrated model
+        let mut data = Vec::new();
+        let sections = ModelSections {
+            counts: &counts,
+            ..ModelSections::new(&header, &fst)
+        };
+        model::write(&mut data, sections).unwrap();
+        let classifier = classifier::NaiveBayesClassifier::from_bytes(&data).unwrap();
+
+        let samples: Vec<_> = held_out
+            .iter()
+            .map(|(email, label)| (classifier.classify_log_odds(email), label != ham_label))
+            .collect();
+
+        let header = ModelHeader {
+            calibration: Some(Calibration::fit(method, &samples)),
+            calibration_alpha: Some(classifier.alpha()),
+            ..header
+        };
+
+        (header, counts, fst)
+    }
+}
+
 fn main() {
     let input_path = std::env::args()
         .nth(1)
@@ -66,11 +346,39 @@ fn main() {
     let output_path = std::env::args()
         .nth(2)
         .expect("Should have output as second argument");
+    let ngram_order = option("--ngram-order").map(|value| {
+        value
+            .parse::<usize>()
+            .ok()
+            .filter(|order| *order >= 1)
+            .expect("N-gram order should be a positive integer")
+    });
+    let mut calibration = option("--calibration").map(|value| {
+        value
+            .parse::<CalibrationMethod>()
+            .expect("Calibration should be platt or isotonic")
+    });
+    let holdout = option("--holdout").map_or(DEFAULT_HOLDOUT, |value| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|fraction| (0.0..1.0).contains(fraction))
+            .expect("Holdout should be a fraction between 0 and 1")
+    });
+
+    let blocklist = option("--blocklist")
+        .map(|path| std::fs::read_to_string(path).expect("Could not read blocklist"));
+    let allowlist = option("--allowlist")
+        .map(|path| std::fs::read_to_string(path).expect("Could not read allowlist"));
+
+    let mut tokenizer = classifier::TokenizerConfig::latest();
+    let mut domain_lists = Vec::new();
 
     // Build counters
     println!("Building token counters...");
 
-    let mut counters: HashMap<String, classifier::Counter> = HashMap::with_capacity(256);
+    let mut global = ModelBuilder::default();
+    let mut languages: BTreeMap<String, ModelBuilder> = BTreeMap::new();
     let mut stats = TrainingStats::new();
 
     // Extend with model if exists
@@ -78,75 +386,167 @@ fn main() {
         println!("Loading existing model...");
 
         let data = std::fs::read(&output_path).unwrap();
-        let map = fst::Map::new(data).unwrap();
+        let file = ModelFile::parse(&data).unwrap();
 
-        let mut stream = map.stream();
-        while let Some((key, value)) = stream.next() {
-            let key = String::from_utf8(key.to_vec()).unwrap();
-            let counter = classifier::Counter::from_u64(value);
+        // Keep tokenizing the way the existing model was trained
+        if let Some(header) = &file.header {
+            if let Err(err) = header.check_tokenizer() {
+                panic!("Can't extend the existing model: {err}");
+            }
+            tokenizer = header.tokenizer.clone();
+
+            // Keep calibrating the way the existing model was
+            if calibration.is_none() {
+                calibration = header.calibration.as_ref().map(Calibration::method);
+            }
+        } else {
+            if let Err(err) = model::check_baseline_tokenizer() {
+                panic!("Can't extend the existing model: {err}");
+            }
+            tokenizer = classifier::TokenizerConfig::default();
+        }
 
-            counters.insert(key, counter);
-            stats.total_tokens += counter.spam + counter.ham;
-            stats.unique_tokens += 1;
+        // Keep the existing domain lists unless new ones are given
+        domain_lists = file.domain_lists.to_vec();
+
+        global = ModelBuilder::load(&file);
+        for (language, data) in &file.languages {
+            let file = ModelFile::parse(data).unwrap();
+            languages.insert(language.clone(), ModelBuilder::load(&file));
         }
+
+        stats.total_tokens = global.total_tokens();
     }
 
-    // Read dataset
-    println!("Reading training dataset...");
+    match ngram_order {
+        Some(order) if global.is_empty() => tokenizer.ngram_order = order,
+        Some(order) if order != tokenizer.ngram_order => panic!(
+            "Existing model uses n-gram order {}, can't extend it with order {}",
+            tokenizer.ngram_order, order
+        ),
+        _ => {}
+    }
+    println!("N-gram order: {}", tokenizer.ngram_order);
+    println!("URL features: {}", tokenizer.urls);
+    println!("Obfuscation normalization: {}", tokenizer.deobfuscate);
+    println!("Stylometric features: {}", tokenizer.style);
+    println!("Placeholders: {}", tokenizer.placeholders);
+    println!("Tokenizer fingerprint: {}", tokenizer.fingerprint());
+    if let Some(method) = calibration {
+        println!(
+            "Calibration: {:?}, holding out {:.0}% of the dataset",
+            method,
+            holdout * 100.0
+        );
+    }
 
-    let file = File::open(input_path).expect("Could not open file");
-    let reader = io::BufReader::new(file);
+    if blocklist.is_some() || allowlist.is_some() {
+        domain_lists = domains::build(
+            blocklist.as_deref().unwrap_or_default().lines(),
+            allowlist.as_deref().unwrap_or_default().lines(),
+        )
+        .unwrap();
+    }
+    let listed = DomainLists::from_bytes(&domain_lists).unwrap().len();
+    if listed > 0 {
+        println!("Domain lists: {listed} domains");
+    }
 
-    let reader = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_reader(reader);
+    // Sub-models of an existing model keep being trained
+    if let Some(codes) = option("--languages") {
+        for code in codes
+            .split(',')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+        {
+            languages.entry(code.to_string()).or_default();
+        }
+    }
+    if !languages.is_empty() {
+        println!(
+            "Languages: {}",
+            languages.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
 
-    let records = reader.into_records().filter_map(|record| record.ok());
-    for record in records {
-        let text = record.get(TEXT_INDEX).unwrap();
-        let label = record.get(LABEL_INDEX).unwrap();
+    // Read dataset
+    println!("Reading training dataset...");
 
-        let (is_spam, is_ham) = (label == LABEL_SPAM, label == LABEL_HAM);
+    for (email, label) in read_dataset(&input_path) {
+        let label = label.as_str();
 
-        if is_spam {
-            stats.spam_samples += 1;
-        } else if is_ham {
-            stats.ham_samples += 1;
-        }
+        *stats.label_samples.entry(label.to_string()).or_default() += 1;
         stats.total_samples += 1;
 
-        let tokens = classifier::tokenize(text);
+        let language = if languages.is_empty() {
+            None
+        } else {
+            languages.get_mut(email.detect_language(&tokenizer).unwrap_or_default())
+        };
+        let builders = std::iter::once(&mut global).chain(language);
+
+        if calibration.is_some() && is_held_out(&email, holdout) {
+            stats.held_out_samples += 1;
+            for builder in builders {
+                builder.hold_out(label, &email);
+            }
+            continue;
+        }
+
+        let tokens = email.tokenize(&tokenizer);
         stats.total_tokens += tokens.len() as u32;
+        stats.trained_tokens += tokens.len() as u32;
+        stats.trained_samples += 1;
 
-        for token in tokens {
-            let counter = counters.entry(token).or_default();
+        for builder in builders {
+            builder.add_document(label, &tokens);
+        }
+    }
 
-            if is_spam {
-                counter.spam += 1;
-            } else if is_ham {
-                counter.ham += 1;
-            }
+    let ham_label = global.ham_label().unwrap_or_else(|| {
+        println!("WARNING: no ham label found, every label will count as spam");
+        LABEL_HAM
+    });
+
+    for (label, samples) in &stats.label_samples {
+        if label == ham_label {
+            stats.ham_samples += samples;
+        } else {
+            stats.spam_samples += samples;
         }
     }
 
-    stats.unique_tokens = counters.len() as u32;
-    stats.avg_tokens_per_sample = stats.total_tokens as f32 / stats.total_samples as f32;
+    stats.unique_tokens = global.counters.len() as u32;
+    stats.avg_tokens_per_sample = stats.trained_tokens as f32 / stats.trained_samples.max(1) as f32;
     stats.print();
 
-    let mut counters: Vec<_> = counters.into_iter().collect();
-    counters.sort_by(|(left, _), (right, _)| left.cmp(right));
-
-    // Build FST model
+    // Build FST models
     println!("Building FST model...");
 
-    let writer = io::BufWriter::new(File::create(&output_path).unwrap());
-    let mut builder = fst::MapBuilder::new(writer).unwrap();
-
-    for (word, counter) in counters {
-        builder.insert(word, counter.to_u64()).unwrap();
-    }
+    let submodels: Vec<_> = languages
+        .into_iter()
+        .map(|(language, builder)| {
+            let (header, counts, fst) = builder.build(&tokenizer, calibration);
+            let mut data = Vec::new();
+            let sections = ModelSections {
+                counts: &counts,
+                ..ModelSections::new(&header, &fst)
+            };
+            model::write(&mut data, sections).unwrap();
+            (language, data)
+        })
+        .collect();
+
+    let (header, counts, fst) = global.build(&tokenizer, calibration);
 
-    builder.finish().unwrap();
+    let writer = io::BufWriter::new(File::create(&output_path).unwrap());
+    let sections = ModelSections {
+        domain_lists: &domain_lists,
+        languages: &submodels,
+        counts: &counts,
+        ..ModelSections::new(&header, &fst)
+    };
+    model::write(writer, sections).unwrap();
     println!("Model saved to: {}", output_path);
 
     // Validate model
@@ -154,19 +554,68 @@ fn main() {
     validate_model(&output_path, &stats);
 }
 
+/// Read the labeled documents of a dataset
+///
+/// `.jsonl` datasets hold one email per line, with a `label` and the fields
+/// of an email. Other datasets are CSV files of texts and labels, the texts
+/// being read as email bodies.
+fn read_dataset(path: &str) -> Box<dyn Iterator<Item = (Email, String)>> {
+    use std::io::BufRead;
+
+    let file = File::open(path).expect("Could not open file");
+    let reader = io::BufReader::new(file);
+
+    if path.ends_with(".jsonl") {
+        let records = reader
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str::<EmailRecord>(&line) {
+                Ok(record) => Some((record.email, record.label)),
+                Err(err) => {
+                    println!("WARNING: skipping invalid record: {err}");
+                    None
+                }
+            });
+        return Box::new(records);
+    }
+
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(reader);
+
+    let records = reader
+        .into_records()
+        .filter_map(|record| record.ok())
+        .map(|record| {
+            let text = record.get(TEXT_INDEX).unwrap();
+            let label = record.get(LABEL_INDEX).unwrap();
+            (Email::from_body(text), label.to_string())
+        });
+    Box::new(records)
+}
+
 fn validate_model(model_path: &str, _stats: &TrainingStats) {
     let data = std::fs::read(model_path).unwrap();
-    let map = fst::Map::new(&data).unwrap();
+    let file = ModelFile::parse(&data).unwrap();
+    let header = file.header.clone().expect("Model should have a header");
+    let table = LabelTable::new(header.labels.clone(), &header.ham_label, file.counts);
+    let map = fst::Map::new(file.fst).unwrap();
 
     let mut total_spam = 0u32;
     let mut total_ham = 0u32;
+    let mut label_tokens = vec![0u32; header.labels.len()];
     let mut unique_tokens = 0u32;
 
     let mut stream = map.stream();
     while let Some((_, value)) = stream.next() {
-        let counter = classifier::Counter::from_u64(value);
+        let counter = table.counter(value);
         total_spam += counter.spam;
         total_ham += counter.ham;
+
+        for (label, tokens) in label_tokens.iter_mut().enumerate() {
+            *tokens += table.count(value, label);
+        }
         unique_tokens += 1;
     }
 
@@ -175,9 +624,43 @@ fn validate_model(model_path: &str, _stats: &TrainingStats) {
     println!("Total ham tokens in model: {}", total_ham);
     println!("Unique tokens in model: {}", unique_tokens);
     println!("Model size: {:.2} MB", data.len() as f32 / 1024.0 / 1024.0);
+    println!("Spam documents: {}", header.spam_documents);
+    println!("Ham documents: {}", header.ham_documents);
+    for (label, tokens) in header.labels.iter().zip(&label_tokens) {
+        println!(
+            "Label {}: {} documents, {} tokens",
+            label.name, label.documents, tokens
+        );
+    }
+
+    let header_label_tokens: Vec<_> = header.labels.iter().map(|label| label.tokens).collect();
+    if (total_spam, total_ham, unique_tokens)
+        != (
+            header.spam_tokens,
+            header.ham_tokens,
+            header.vocabulary_size,
+        )
+        || label_tokens != header_label_tokens
+    {
+        println!("WARNING: header totals don't match the FST content");
+    }
+
+    for (language, data) in &file.languages {
+        let submodel = ModelFile::parse(data).unwrap();
+        let submodel = submodel.header.expect("Sub-model should have a header");
+        println!(
+            "Language {}: {} spam documents, {} ham documents, {} unique tokens",
+            language, submodel.spam_documents, submodel.ham_documents, submodel.vocabulary_size
+        );
+    }
+
+    match &header.calibration {
+        Some(calibration) => println!("Calibration: {:?}", calibration.method()),
+        None => println!("Calibration: none"),
+    }
 
     // Calculate some basic statistics
-    let prior_spam = total_spam as f64 / (total_spam + total_ham) as f64;
+    let prior_spam = classifier::ClassifierStats::from_header(&header).prior_spam();
     println!("Prior P(spam): {:.3}", prior_spam);
     println!("Prior P(ham): {:.3}", 1.0 - prior_spam);
     println!("=======================");
@@ -0,0 +1,343 @@
+//! Probability calibration
+//!
+//! Naive Bayes posteriors are pushed towards 0 and 1 by the independence
+//! assumption, so the same threshold means something different for every
+//! model. The trainer fits a map from log-odds to calibrated probabilities on
+//! documents held out from training, and stores it in the model header.
+
+/// Map from multinomial log-odds to calibrated spam probabilities
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Calibration {
+    /// Platt scaling, `1 / (1 + exp(a * log_odds + b))`
+    Platt { a: f64, b: f64 },
+    /// Isotonic regression, a non-decreasing piecewise linear map
+    Isotonic {
+        /// Log-odds, sorted in increasing order
+        thresholds: Vec<f64>,
+        /// Calibrated probabilities at each threshold
+        probabilities: Vec<f64>,
+    },
+}
+
+/// Calibration methods the trainer can fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationMethod {
+    Platt,
+    Isotonic,
+}
+
+impl std::str::FromStr for CalibrationMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "platt" => Ok(Self::Platt),
+            "isotonic" => Ok(Self::Isotonic),
+            _ => anyhow::bail!("Unknown calibration method: {value}"),
+        }
+    }
+}
+
+impl Calibration {
+    /// Fit a calibration map on `(log_odds, is_spam)` samples
+    #[allow(dead_code)]
+    pub fn fit(method: CalibrationMethod, samples: &[(f64, bool)]) -> Self {
+        match method {
+            CalibrationMethod::Platt => fit_platt(samples),
+            CalibrationMethod::Isotonic => fit_isotonic(samples),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn method(&self) -> CalibrationMethod {
+        match self {
+            Self::Platt { .. } => CalibrationMethod::Platt,
+            Self::Isotonic { .. } => CalibrationMethod::Isotonic,
+        }
+    }
+
+    /// Get the calibrated spam probability of a log-odds score
+    pub fn apply(&self, log_odds: f64) -> f64 {
+        match self {
+            Self::Platt { a, b } => 1.0 / (1.0 + (a * log_odds + b).exp()),
+            Self::Isotonic {
+                thresholds,
+                probabilities,
+            } => interpolate(thresholds, probabilities, log_odds),
+        }
+    }
+}
+
+/// Fit Platt scaling with the Newton method of Lin, Lin and Weng (2007)
+fn fit_platt(samples: &[(f64, bool)]) -> Calibration {
+    const MAX_ITERATIONS: usize = 100;
+    const MIN_STEP: f64 = 1e-10;
+    const SIGMA: f64 = 1e-12;
+    const EPSILON: f64 = 1e-5;
+
+    let positives = samples.iter().filter(|(_, is_spam)| *is_spam).count() as f64;
+    let negatives = samples.len() as f64 - positives;
+
+    // Smoothed targets, avoiding overfitting on separable data
+    let high_target = (positives + 1.0) / (positives + 2.0);
+    let low_target = 1.0 / (negatives + 2.0);
+    let targets: Vec<f64> = samples
+        .iter()
+        .map(|(_, is_spam)| if *is_spam { high_target } else { low_target })
+        .collect();
+
+    let objective = |a: f64, b: f64| -> f64 {
+        samples
+            .iter()
+            .zip(&targets)
+            .map(|((score, _), target)| {
+                let value = score * a + b;
+                if value >= 0.0 {
+                    target * value + (1.0 + (-value).exp()).ln()
+                } else {
+                    (target - 1.0) * value + (1.0 + value.exp()).ln()
+                }
+            })
+            .sum()
+    };
+
+    let mut a = 0.0;
+    let mut b = ((negatives + 1.0) / (positives + 1.0)).ln();
+    let mut value = objective(a, b);
+
+    for _ in 0..MAX_ITERATIONS {
+        // Gradient and Hessian of the negative log-likelihood
+        let (mut h11, mut h22, mut h21, mut g1, mut g2) = (SIGMA, SIGMA, 0.0, 0.0, 0.0);
+        for ((score, _), target) in samples.iter().zip(&targets) {
+            let value = score * a + b;
+            let (p, q) = if value >= 0.0 {
+                let t = (-value).exp();
+                (t / (1.0 + t), 1.0 / (1.0 + t))
+            } else {
+                let t = value.exp();
+                (1.0 / (1.0 + t), t / (1.0 + t))
+            };
+            let d2 = p * q;
+            h11 += score * score * d2;
+            h22 += d2;
+            h21 += score * d2;
+            let d1 = target - p;
+            g1 += score * d1;
+            g2 += d1;
+        }
+
+        if g1.abs() < EPSILON && g2.abs() < EPSILON {
+            break;
+        }
+
+        let determinant = h11 * h22 - h21 * h21;
+        let da = -(h22 * g1 - h21 * g2) / determinant;
+        let db = -(-h21 * g1 + h11 * g2) / determinant;
+        let gd = g1 * da + g2 * db;
+
+        // Backtracking line search
+        let mut step = 1.0;
+        while step >= MIN_STEP {
+            let (new_a, new_b) = (a + step * da, b + step * db);
+            let new_value = objective(new_a, new_b);
+            if new_value < value + 0.0001 * step * gd {
+                (a, b, value) = (new_a, new_b, new_value);
+                break;
+            }
+            step /= 2.0;
+        }
+
+        if step < MIN_STEP {
+            break;
+        }
+    }
+
+    Calibration::Platt { a, b }
+}
+
+/// Fit isotonic regression with the pool adjacent violators algorithm
+fn fit_isotonic(samples: &[(f64, bool)]) -> Calibration {
+    let mut sorted: Vec<_> = samples.to_vec();
+    sorted.sort_by(|left, right| left.0.total_cmp(&right.0));
+
+    // Blocks of (lowest score, highest score, sum of labels, size)
+    let mut blocks: Vec<(f64, f64, f64, f64)> = Vec::new();
+    for (score, is_spam) in sorted {
+        blocks.push((score, score, if is_spam { 1.0 } else { 0.0 }, 1.0));
+
+        while blocks.len() > 1 {
+            let (low, high, sum, size) = blocks[blocks.len() - 1];
+            let previous = blocks[blocks.len() - 2];
+
+            // Equal scores must share a value, and values must not decrease
+            if previous.1 != low && previous.2 / previous.3 < sum / size {
+                break;
+            }
+
+            blocks.pop();
+            let last = blocks.last_mut().unwrap();
+            *last = (previous.0, high, previous.2 + sum, previous.3 + size);
+        }
+    }
+
+    let mut thresholds = Vec::with_capacity(blocks.len() * 2);
+    let mut probabilities = Vec::with_capacity(blocks.len() * 2);
+    for (low, high, sum, size) in blocks {
+        let probability = sum / size;
+
+        thresholds.push(low);
+        probabilities.push(probability);
+        if high > low {
+            thresholds.push(high);
+            probabilities.push(probability);
+        }
+    }
+
+    Calibration::Isotonic {
+        thresholds,
+        probabilities,
+    }
+}
+
+/// Linear interpolation through sorted points, constant beyond both ends
+fn interpolate(thresholds: &[f64], probabilities: &[f64], value: f64) -> f64 {
+    let Some(last) = thresholds.len().checked_sub(1) else {
+        return 0.5;
+    };
+
+    let index = thresholds.partition_point(|threshold| *threshold <= value);
+    if index == 0 {
+        return probabilities[0];
+    }
+    if index > last {
+        return probabilities[last];
+    }
+
+    let (x0, x1) = (thresholds[index - 1], thresholds[index]);
+    let (y0, y1) = (probabilities[index - 1], probabilities[index]);
+    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::super::model::ModelHeader;
+    use super::super::Counter;
+    use super::*;
+
+    fn build_model(calibration: Calibration, calibration_alpha: Option<f64>) -> Vec<u8> {
+        fixtures::build_model(
+            &[
+                ("free", Counter { spam: 9, ham: 1 }),
+                ("lunch", Counter { spam: 1, ham: 9 }),
+            ],
+            ModelHeader {
+                spam_documents: 5,
+                ham_documents: 5,
+                calibration: Some(calibration),
+                calibration_alpha,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Samples whose spam rate rises with the score: 1 in 5 at -2, 4 in 5 at 2
+    fn samples() -> Vec<(f64, bool)> {
+        let mut samples = Vec::new();
+        for (score, spam) in [(-2.0, 1), (0.0, 3), (2.0, 4)] {
+            for i in 0..5 {
+                samples.push((score, i < spam));
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn test_platt() {
+        let calibration = Calibration::fit(CalibrationMethod::Platt, &samples());
+        assert_eq!(calibration.method(), CalibrationMethod::Platt);
+
+        let low = calibration.apply(-2.0);
+        let high = calibration.apply(2.0);
+        assert!(low < calibration.apply(0.0) && calibration.apply(0.0) < high);
+        assert!((low - 0.2).abs() < 0.1, "calibrated {low}");
+        assert!((high - 0.8).abs() < 0.1, "calibrated {high}");
+    }
+
+    #[test]
+    fn test_isotonic() {
+        let mut samples = samples();
+        // A violator, merged into its neighbour
+        samples.push((1.0, false));
+
+        let calibration = Calibration::fit(CalibrationMethod::Isotonic, &samples);
+        let Calibration::Isotonic {
+            thresholds,
+            probabilities,
+        } = &calibration
+        else {
+            panic!("Expected an isotonic calibration");
+        };
+
+        assert!(thresholds.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(probabilities.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        assert_eq!(calibration.apply(-10.0), 0.2);
+        assert_eq!(calibration.apply(-2.0), 0.2);
+        assert_eq!(calibration.apply(10.0), 0.8);
+        assert!((calibration.apply(0.5) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let calibration = Calibration::Platt { a: -1.5, b: 0.25 };
+        let json = serde_json::to_value(&calibration).unwrap();
+        assert_eq!(json["method"], "platt");
+
+        let deserialized: Calibration = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, calibration);
+    }
+
+    #[test]
+    fn test_classifier_applies_calibration() {
+        let data = build_model(Calibration::Platt { a: -0.5, b: 0.0 }, None);
+        let mut classifier = fixtures::classifier(&data, super::super::ScoringMode::Multinomial);
+
+        let log_odds = classifier.classify_log_odds("free");
+        let calibrated = classifier.classify("free");
+        assert!((calibrated - 1.0 / (1.0 + (-0.5 * log_odds).exp())).abs() < 1e-12);
+        assert_eq!(
+            classifier.classify_detailed("free").spam_probability,
+            calibrated
+        );
+
+        // Other scoring modes are left uncalibrated
+        classifier.set_mode(super::super::ScoringMode::Complement);
+        assert!((classifier.classify("free") - calibrated).abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_calibration_alpha() {
+        let data = build_model(Calibration::Platt { a: -0.5, b: 0.0 }, None);
+        let mut classifier = fixtures::classifier(&data, super::super::ScoringMode::Multinomial);
+        let calibrated = classifier.classify("free");
+
+        // Maps fitted with another smoothing factor don't fit the scores
+        classifier.set_alpha(0.5);
+        let log_odds = classifier.classify_log_odds("free");
+        let expected = 1.0 / (1.0 + (-log_odds).exp());
+        assert!((classifier.classify("free") - expected).abs() < 1e-12);
+
+        classifier.set_alpha(super::super::DEFAULT_ALPHA);
+        assert_eq!(classifier.classify("free"), calibrated);
+
+        // Models record the smoothing factor their map was fitted with
+        let data = build_model(Calibration::Platt { a: -0.5, b: 0.0 }, Some(0.5));
+        let mut classifier = fixtures::classifier(&data, super::super::ScoringMode::Multinomial);
+        classifier.set_alpha(0.5);
+        let log_odds = classifier.classify_log_odds("free");
+        let expected = 1.0 / (1.0 + (-0.5 * log_odds).exp());
+        assert!((classifier.classify("free") - expected).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,250 @@
+//! Robinson/Fisher chi-squared combining, as proven out by SpamBayes
+//!
+//! Each token gets Robinson's f(w), a spam probability shrunk towards a prior
+//! for rarely seen tokens. Fisher's method then combines them into separate
+//! spam and ham evidences, so that texts with strong evidence both ways score
+//! around 0.5 instead of being pushed to an extreme.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+use super::{Counter, NaiveBayesClassifier};
+
+/// Tokens with f(w) closer to 0.5 than this are ignored
+pub const MIN_PROB_STRENGTH: f64 = 0.1;
+
+/// SpamBayes cutoffs, the default decision thresholds of the chi-squared mode
+pub const HAM_CUTOFF: f64 = 0.20;
+pub const SPAM_CUTOFF: f64 = 0.90;
+
+/// Robinson parameters
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChiSquaredConfig {
+    /// Weight of the prior against the token observations, Robinson's s
+    pub strength: f64,
+    /// Spam probability assumed for unseen tokens, Robinson's x
+    pub prior: f64,
+}
+
+impl ChiSquaredConfig {
+    pub fn new(strength: f64, prior: f64) -> Result<Self> {
+        // A null strength leaves unseen tokens at 0 / 0
+        if !(strength.is_finite() && strength > 0.0) {
+            anyhow::bail!("robinson_strength should be a positive number, got {strength}");
+        }
+        if !(0.0..=1.0).contains(&prior) {
+            anyhow::bail!("robinson_prior should be between 0.0 and 1.0, got {prior}");
+        }
+
+        Ok(Self { strength, prior })
+    }
+}
+
+impl Default for ChiSquaredConfig {
+    fn default() -> Self {
+        Self {
+            strength: 0.45,
+            prior: 0.5,
+        }
+    }
+}
+
+/// Combined evidences of a text
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct ChiSquaredScore {
+    /// Spam evidence, close to 1 when many tokens lean towards spam
+    pub spam: f64,
+    /// Ham evidence, close to 1 when many tokens lean towards ham
+    pub ham: f64,
+    /// Combined score in [0, 1], `(spam - ham + 1) / 2`
+    pub probability: f64,
+}
+
+impl<D: AsRef<[u8]>> NaiveBayesClassifier<D> {
+    /// Combine the distinct tokens of a text with Fisher's method
+    pub(super) fn chi_squared_score(&self, tokens: &[String]) -> ChiSquaredScore {
+        let mut seen = HashSet::new();
+        let (mut log_spam, mut log_ham, mut count) = (0.0, 0.0, 0);
+
+        for token in tokens.iter().filter(|token| seen.insert(token.as_str())) {
+            let probability = self.robinson_probability(&self.get_token_counter(token));
+            if (probability - 0.5).abs() < MIN_PROB_STRENGTH {
+                continue;
+            }
+
+            log_spam += (1.0 - probability).ln();
+            log_ham += probability.ln();
+            count += 1;
+        }
+
+        if count == 0 {
+            return ChiSquaredScore {
+                spam: 0.0,
+                ham: 0.0,
+                probability: 0.5,
+            };
+        }
+
+        let spam = 1.0 - chi2q(-2.0 * log_spam, 2 * count);
+        let ham = 1.0 - chi2q(-2.0 * log_ham, 2 * count);
+
+        ChiSquaredScore {
+            spam,
+            ham,
+            probability: (spam - ham + 1.0) / 2.0,
+        }
+    }
+
+    /// Calculate Robinson's f(w) for a token counter
+    pub(super) fn robinson_probability(&self, counter: &Counter) -> f64 {
+        let config = &self.chi_squared;
+
+        // Normalize by class size so that imbalanced data doesn't skew p(w)
+        let (spam_size, ham_size) = if self.stats.spam_documents + self.stats.ham_documents > 0 {
+            (self.stats.spam_documents, self.stats.ham_documents)
+        } else {
+            (self.stats.total_spam, self.stats.total_ham)
+        };
+        let spam_ratio = counter.spam as f64 / spam_size.max(1) as f64;
+        let ham_ratio = counter.ham as f64 / ham_size.max(1) as f64;
+
+        let observations = (counter.spam + counter.ham) as f64;
+        let probability = if observations == 0.0 {
+            config.prior
+        } else {
+            spam_ratio / (spam_ratio + ham_ratio)
+        };
+
+        (config.strength * config.prior + observations * probability)
+            / (config.strength + observations)
+    }
+}
+
+/// Probability that a chi-squared variable with `degrees` (even) degrees of freedom exceeds `value`
+fn chi2q(value: f64, degrees: usize) -> f64 {
+    let mean = value / 2.0;
+    let mut term = (-mean).exp();
+    let mut sum = term;
+
+    for i in 1..degrees / 2 {
+        term *= mean / i as f64;
+        sum += term;
+    }
+
+    sum.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decision::{DecisionThresholds, Verdict};
+    use super::super::fixtures;
+    use super::super::model::ModelHeader;
+    use super::super::ScoringMode;
+    use super::*;
+
+    fn build_model() -> Vec<u8> {
+        fixtures::build_model(
+            &[
+                ("free", Counter { spam: 40, ham: 2 }),
+                ("lunch", Counter { spam: 1, ham: 40 }),
+                ("money", Counter { spam: 30, ham: 3 }),
+                ("today", Counter { spam: 2, ham: 30 }),
+            ],
+            ModelHeader {
+                spam_documents: 50,
+                ham_documents: 50,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn classifier(data: &[u8]) -> NaiveBayesClassifier<&[u8]> {
+        let mut classifier = fixtures::classifier(data, ScoringMode::ChiSquared);
+        classifier
+            .set_decision_thresholds(DecisionThresholds::new(HAM_CUTOFF, SPAM_CUTOFF).unwrap());
+        classifier
+    }
+
+    fn verdict(classifier: &NaiveBayesClassifier<&[u8]>, text: &str) -> Verdict {
+        classifier.classify_detailed(text).verdict
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(ChiSquaredConfig::new(0.45, 0.5).is_ok());
+        assert!(ChiSquaredConfig::new(1.0, 0.0).is_ok());
+        assert!(ChiSquaredConfig::new(0.0, 0.5).is_err());
+        assert!(ChiSquaredConfig::new(-1.0, 0.5).is_err());
+        assert!(ChiSquaredConfig::new(f64::NAN, 0.5).is_err());
+        assert!(ChiSquaredConfig::new(0.45, 1.5).is_err());
+        assert!(ChiSquaredConfig::new(0.45, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_chi2q() {
+        // With 2 degrees of freedom, the survival function is exp(-x / 2)
+        assert!((chi2q(3.0, 2) - (-1.5f64).exp()).abs() < 1e-12);
+        assert_eq!(chi2q(0.0, 10), 1.0);
+        assert!(chi2q(200.0, 4) < 1e-12);
+    }
+
+    #[test]
+    fn test_robinson_probability() {
+        let data = build_model();
+        let classifier = classifier(&data);
+
+        // Unseen tokens get the prior
+        assert_eq!(classifier.robinson_probability(&Counter::default()), 0.5);
+
+        // Rare tokens are shrunk towards the prior more than frequent ones
+        let rare = classifier.robinson_probability(&Counter { spam: 1, ham: 0 });
+        let frequent = classifier.robinson_probability(&Counter { spam: 20, ham: 0 });
+        assert!(0.5 < rare && rare < frequent && frequent < 1.0);
+    }
+
+    #[test]
+    fn test_verdicts() {
+        let data = build_model();
+        let classifier = classifier(&data);
+
+        assert_eq!(verdict(&classifier, "free money"), Verdict::Spam);
+        assert_eq!(verdict(&classifier, "lunch today"), Verdict::Ham);
+        assert_eq!(
+            verdict(&classifier, "free money lunch today"),
+            Verdict::NeedsReview
+        );
+        assert!((classifier.classify("free money lunch today") - 0.5).abs() < 0.1);
+
+        // Mixed texts carry strong evidence both ways
+        let tokens = classifier.tokenizer().tokenize("free money lunch today");
+        let score = classifier.chi_squared_score(&tokens);
+        assert!(score.spam > 0.5 && score.ham > 0.5);
+
+        // Texts without any strong token need a review
+        assert!((classifier.classify("hello") - 0.5).abs() < 1e-9);
+        assert_eq!(verdict(&classifier, "hello"), Verdict::NeedsReview);
+    }
+
+    #[test]
+    fn test_explanation() {
+        let data = build_model();
+        let classifier = classifier(&data);
+
+        let explanation = classifier
+            .classify_explained("free hello lunch", 5)
+            .explanation
+            .unwrap();
+        let log_odds: Vec<_> = explanation
+            .tokens
+            .iter()
+            .map(|contribution| contribution.log_likelihood_ratio)
+            .collect();
+
+        // Tokens too neutral to be combined don't contribute
+        assert!(log_odds[0] > 0.0);
+        assert_eq!(log_odds[1], 0.0);
+        assert!(log_odds[2] < 0.0);
+    }
+}
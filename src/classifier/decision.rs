@@ -0,0 +1,187 @@
+//! Three-way decisions
+//!
+//! On top of the binary `is_spam`, scores are split into ham, spam and a grey
+//! zone in between, left for manual review.
+//!
+//! When misclassification costs are given, `is_spam` is the action with the
+//! lowest expected cost under the spam probability instead.
+
+use anyhow::Result;
+
+/// Decision made on a spam probability
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Ham,
+    NeedsReview,
+    Spam,
+}
+
+/// Probabilities splitting ham, needs review and spam verdicts
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DecisionThresholds {
+    /// Probabilities below this are ham
+    pub ham_below: f64,
+    /// Probabilities at or above this are spam
+    pub spam_above: f64,
+}
+
+impl DecisionThresholds {
+    pub fn new(ham_below: f64, spam_above: f64) -> Result<Self> {
+        for (name, value) in [("ham_below", ham_below), ("spam_above", spam_above)] {
+            if !(0.0..=1.0).contains(&value) {
+                anyhow::bail!("{name} should be between 0.0 and 1.0, got {value}");
+            }
+        }
+        if ham_below > spam_above {
+            anyhow::bail!(
+                "ham_below ({ham_below}) should not be greater than spam_above ({spam_above})"
+            );
+        }
+
+        Ok(Self {
+            ham_below,
+            spam_above,
+        })
+    }
+
+    /// Thresholds without a grey zone, splitting ham and spam at a single probability
+    pub fn single(threshold: f64) -> Self {
+        Self {
+            ham_below: threshold,
+            spam_above: threshold,
+        }
+    }
+
+    pub fn verdict(&self, spam_probability: f64) -> Verdict {
+        if spam_probability >= self.spam_above {
+            Verdict::Spam
+        } else if spam_probability < self.ham_below {
+            Verdict::Ham
+        } else {
+            Verdict::NeedsReview
+        }
+    }
+}
+
+/// Shift a probability by an amount of log-odds, positive amounts towards spam
+pub fn shift_log_odds(probability: f64, shift: f64) -> f64 {
+    let log_odds = (probability / (1.0 - probability)).ln() + shift;
+    1.0 / (1.0 + (-log_odds).exp())
+}
+
+/// Costs of misclassifying a message
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MisclassificationCosts {
+    /// Cost of classifying a ham message as spam
+    pub false_positive: f64,
+    /// Cost of classifying a spam message as ham
+    pub false_negative: f64,
+}
+
+/// Expected cost of each action given a spam probability
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct ExpectedCosts {
+    /// Expected cost of classifying the message as spam
+    pub spam: f64,
+    /// Expected cost of classifying the message as ham
+    pub ham: f64,
+}
+
+impl MisclassificationCosts {
+    pub fn new(false_positive: f64, false_negative: f64) -> Result<Self> {
+        for (name, value) in [
+            ("false_positive_cost", false_positive),
+            ("false_negative_cost", false_negative),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                anyhow::bail!("{name} should be a non-negative number, got {value}");
+            }
+        }
+        if false_positive + false_negative == 0.0 {
+            anyhow::bail!("false_positive_cost and false_negative_cost can't both be 0");
+        }
+
+        Ok(Self {
+            false_positive,
+            false_negative,
+        })
+    }
+
+    /// Spam probability above which classifying as spam costs less
+    pub fn threshold(&self) -> f64 {
+        self.false_positive / (self.false_positive + self.false_negative)
+    }
+
+    pub fn expected(&self, spam_probability: f64) -> ExpectedCosts {
+        ExpectedCosts {
+            spam: (1.0 - spam_probability) * self.false_positive,
+            ham: spam_probability * self.false_negative,
+        }
+    }
+
+    /// Whether classifying as spam minimizes the expected cost, ties going to spam
+    pub fn is_spam(&self, spam_probability: f64) -> bool {
+        let expected = self.expected(spam_probability);
+        expected.spam <= expected.ham
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds(ham_below: f64, spam_above: f64) -> Result<DecisionThresholds> {
+        DecisionThresholds::new(ham_below, spam_above)
+    }
+
+    #[test]
+    fn test_verdict() {
+        let thresholds = thresholds(0.3, 0.8).unwrap();
+
+        assert_eq!(thresholds.verdict(0.1), Verdict::Ham);
+        assert_eq!(thresholds.verdict(0.3), Verdict::NeedsReview);
+        assert_eq!(thresholds.verdict(0.79), Verdict::NeedsReview);
+        assert_eq!(thresholds.verdict(0.8), Verdict::Spam);
+
+        // A single threshold leaves no grey zone
+        let single = DecisionThresholds::single(0.8);
+        assert_eq!(single.verdict(0.79), Verdict::Ham);
+        assert_eq!(single.verdict(0.8), Verdict::Spam);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(thresholds(0.5, 0.5).is_ok());
+        assert!(thresholds(0.9, 0.2).is_err());
+        assert!(thresholds(-0.1, 0.5).is_err());
+        assert!(thresholds(0.2, 1.5).is_err());
+        assert!(thresholds(f64::NAN, 0.5).is_err());
+    }
+
+    #[test]
+    fn test_costs() {
+        // Blocking a legitimate message costs 9 times more than letting spam through
+        let costs = MisclassificationCosts::new(9.0, 1.0).unwrap();
+        assert!((costs.threshold() - 0.9).abs() < 1e-12);
+
+        let expected = costs.expected(0.8);
+        assert!((expected.spam - 1.8).abs() < 1e-12);
+        assert!((expected.ham - 0.8).abs() < 1e-12);
+        assert!(!costs.is_spam(0.8));
+        assert!(costs.is_spam(0.95));
+
+        assert!(MisclassificationCosts::new(0.0, 1.0).is_ok());
+        assert!(MisclassificationCosts::new(0.0, 0.0).is_err());
+        assert!(MisclassificationCosts::new(-1.0, 1.0).is_err());
+        assert!(MisclassificationCosts::new(f64::INFINITY, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_serialization() {
+        assert_eq!(
+            serde_json::to_value(Verdict::NeedsReview).unwrap(),
+            "needs_review"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Embedded lists of known-bad and known-good domains
+//!
+//! The trainer builds an FST of the domains found in plain-text block and
+//! allow lists, stored in the model container next to the token FST. The
+//! values are bit sets of the lists a domain is on. URLs match an entry when
+//! their domain is the entry itself or one of its subdomains.
+
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use super::decision::shift_log_odds;
+use super::model::ModelFile;
+use super::url::Url;
+use super::MODEL;
+
+const BLOCKLIST_BIT: u64 = 1;
+const ALLOWLIST_BIT: u64 = 2;
+
+/// Log-odds added by a blocklist match, or removed by an allowlist match, in boost mode
+pub const DEFAULT_LIST_BOOST: f64 = 4.0;
+
+/// Lists built from the embedded model on first use and shared by every request
+static SHARED_LISTS: LazyLock<DomainLists<&'static [u8]>> = LazyLock::new(|| {
+    let file = ModelFile::parse(MODEL).unwrap();
+    DomainLists::from_bytes(file.domain_lists).unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainList {
+    Blocklist,
+    Allowlist,
+}
+
+impl DomainList {
+    fn bit(self) -> u64 {
+        match self {
+            Self::Blocklist => BLOCKLIST_BIT,
+            Self::Allowlist => ALLOWLIST_BIT,
+        }
+    }
+}
+
+/// List entry matched by a URL of the input
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ListMatch {
+    pub list: DomainList,
+    /// Entry of the list, the URL domain or one of its parents
+    pub domain: String,
+    /// First URL that matched the entry
+    pub url: String,
+}
+
+/// How list matches affect the classification
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListAction {
+    /// Blocklist matches make the input spam and allowlist matches ham, the
+    /// blocklist winning when both match
+    #[default]
+    Override,
+    /// Shift the log-odds by the given amount towards spam for blocklist
+    /// matches, and towards ham for allowlist matches
+    Boost(f64),
+    /// Report matches without changing the result
+    Off,
+}
+
+impl ListAction {
+    /// Spam probability after the matches, `None` when it is left unchanged
+    pub fn apply(self, spam_probability: f64, matches: &[ListMatch]) -> Option<f64> {
+        let blocked = matches.iter().any(|m| m.list == DomainList::Blocklist);
+        let allowed = matches.iter().any(|m| m.list == DomainList::Allowlist);
+        if !blocked && !allowed {
+            return None;
+        }
+
+        match self {
+            Self::Override => Some(if blocked { 1.0 } else { 0.0 }),
+            Self::Boost(boost) => {
+                let shift = match (blocked, allowed) {
+                    (true, false) => boost,
+                    (false, true) => -boost,
+                    _ => return None,
+                };
+                Some(shift_log_odds(spam_probability, shift))
+            }
+            Self::Off => None,
+        }
+    }
+}
+
+/// Domain lists FST
+#[derive(Clone)]
+pub struct DomainLists<D> {
+    /// `None` for models built without lists
+    map: Option<fst::Map<D>>,
+}
+
+impl DomainLists<&'static [u8]> {
+    /// Get the process-wide lists of the embedded model
+    pub fn shared() -> &'static Self {
+        &SHARED_LISTS
+    }
+}
+
+impl<'a> DomainLists<&'a [u8]> {
+    /// Load lists from the domain lists section of a model container, which may be empty
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        let map = if data.is_empty() {
+            None
+        } else {
+            Some(fst::Map::new(data)?)
+        };
+        Ok(Self { map })
+    }
+}
+
+impl<D: AsRef<[u8]>> DomainLists<D> {
+    /// Number of domains across both lists
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.map.as_ref().map_or(0, |map| map.len())
+    }
+
+    /// Entries matched by the URLs, each reported once with the first URL matching it
+    pub fn matches(&self, urls: &[Url]) -> Vec<ListMatch> {
+        let Some(map) = &self.map else {
+            return Vec::new();
+        };
+
+        let mut matches: Vec<ListMatch> = Vec::new();
+        for url in urls {
+            for domain in candidates(url) {
+                let Some(bits) = map.get(domain) else {
+                    continue;
+                };
+
+                for list in [DomainList::Blocklist, DomainList::Allowlist] {
+                    let known = matches.iter().any(|m| m.list == list && m.domain == domain);
+                    if bits & list.bit() != 0 && !known {
+                        matches.push(ListMatch {
+                            list,
+                            domain: domain.to_string(),
+                            url: url.url.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        matches
+    }
+}
+
+/// Domains a URL can match: its domain and its parents, or its IP address alone
+fn candidates(url: &Url) -> Vec<&str> {
+    if url.ip {
+        return vec![url.domain.as_str()];
+    }
+
+    let mut candidates = vec![url.domain.as_str()];
+    let mut rest = url.domain.as_str();
+    while let Some((_, parent)) = rest.split_once('.') {
+        candidates.push(parent);
+        rest = parent;
+    }
+    candidates
+}
+
+/// Normalize a list entry, `None` for blank lines, comments and invalid entries
+///
+/// Entries may be URLs, and `www.` or `*.` prefixes are ignored.
+#[allow(dead_code)]
+pub fn parse_entry(line: &str) -> Option<String> {
+    let entry = line.split('#').next().unwrap_or_default().trim();
+    if entry.is_empty() || entry.contains(char::is_whitespace) {
+        return None;
+    }
+
+    if entry.contains("://") {
+        return Url::parse(entry).map(|url| url.domain);
+    }
+
+    let entry = entry.to_lowercase();
+    let entry = entry.trim_start_matches("*.").trim_end_matches('.');
+    let entry = entry.strip_prefix("www.").unwrap_or(entry);
+    let valid = !entry.is_empty()
+        && entry.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    valid.then(|| entry.to_string())
+}
+
+/// Build the domain lists FST from the lines of a blocklist and an allowlist
+///
+/// Invalid lines are skipped.
+#[allow(dead_code)]
+pub fn build<'a>(
+    blocklist: impl IntoIterator<Item = &'a str>,
+    allowlist: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut domains: BTreeMap<String, u64> = BTreeMap::new();
+    for domain in blocklist.into_iter().filter_map(parse_entry) {
+        *domains.entry(domain).or_default() |= BLOCKLIST_BIT;
+    }
+    for domain in allowlist.into_iter().filter_map(parse_entry) {
+        *domains.entry(domain).or_default() |= ALLOWLIST_BIT;
+    }
+
+    // Entries are inserted in lexicographic order, as the FST builder requires
+    let mut builder = fst::MapBuilder::memory();
+    for (domain, bits) in domains {
+        builder.insert(domain, bits)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> Vec<u8> {
+        build(
+            [
+                "# Known bad",
+                "evil.example",
+                "*.spam.test",
+                "https://www.Phish.example/login",
+                "not a domain",
+            ],
+            ["example.com", "mail.spam.test"],
+        )
+        .unwrap()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(
+            parse_entry("  WWW.Example.com. # comment"),
+            Some("example.com".into())
+        );
+        assert_eq!(
+            parse_entry("http://evil.example/path"),
+            Some("evil.example".into())
+        );
+        assert_eq!(parse_entry("# comment"), None);
+        assert_eq!(parse_entry("0.0.0.0 evil.example"), None);
+    }
+
+    #[test]
+    fn test_matches() {
+        let data = lists();
+        let lists = DomainLists::from_bytes(&data).unwrap();
+        assert_eq!(lists.len(), 5);
+
+        let matches = lists.matches(&[
+            url("https://login.evil.example/a"),
+            url("https://evil.example/b"),
+            url("https://phish.example"),
+            url("https://notevil.example"),
+        ]);
+        assert_eq!(
+            matches
+                .iter()
+                .map(|m| (m.list, m.domain.as_str(), m.url.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    DomainList::Blocklist,
+                    "evil.example",
+                    "https://login.evil.example/a"
+                ),
+                (
+                    DomainList::Blocklist,
+                    "phish.example",
+                    "https://phish.example"
+                ),
+            ]
+        );
+
+        // Subdomains can be on both lists through different entries
+        let matches = lists.matches(&[url("http://mail.spam.test")]);
+        assert_eq!(matches.len(), 2);
+
+        let empty = DomainLists::from_bytes(&[]).unwrap();
+        assert!(empty.matches(&[url("https://evil.example")]).is_empty());
+    }
+
+    #[test]
+    fn test_actions() {
+        let blocked = [ListMatch {
+            list: DomainList::Blocklist,
+            domain: "evil.example".into(),
+            url: "https://evil.example".into(),
+        }];
+
+        assert_eq!(ListAction::Override.apply(0.2, &blocked), Some(1.0));
+        assert_eq!(ListAction::Off.apply(0.2, &blocked), None);
+        assert_eq!(ListAction::Override.apply(0.2, &[]), None);
+
+        let boosted = ListAction::Boost(DEFAULT_LIST_BOOST)
+            .apply(0.2, &blocked)
+            .unwrap();
+        assert!(boosted > 0.9 && boosted < 1.0);
+    }
+}
@@ -0,0 +1,312 @@
+//! Structured email input
+//!
+//! The tokens of every field but the body get a field-specific key prefix, so
+//! that "free" in a subject is a different feature from "free" in a body. Body
+//! tokens are left unprefixed, sharing their features with plain texts.
+//! HTML bodies contribute their visible text and [HTML features](super::html).
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use super::html::HtmlContent;
+use super::tokenizer::{Document, Token, TokenizerConfig};
+use super::url::{self, Url};
+
+pub const SUBJECT_PREFIX: &str = "subject:";
+pub const FROM_PREFIX: &str = "from:";
+pub const TO_PREFIX: &str = "to:";
+
+/// Key prefix of header features, followed by the lowercased header name and a colon
+pub const HEADER_PREFIX: &str = "header:";
+
+/// An email split into its fields
+///
+/// Emails are deserialized from objects with known fields only, at least one
+/// of them set, so that other objects aren't classified as empty emails.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "EmailFields")]
+pub struct Email {
+    pub subject: String,
+    #[serde(alias = "text")]
+    pub body: String,
+    pub from: String,
+    pub to: String,
+    /// Other headers, by name
+    pub headers: BTreeMap<String, String>,
+    /// Whether the body is HTML
+    pub html: bool,
+}
+
+/// Fields of an email as deserialized, before checking that one is set
+#[derive(Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EmailFields {
+    subject: String,
+    #[serde(alias = "text")]
+    body: String,
+    from: String,
+    to: String,
+    headers: BTreeMap<String, String>,
+    html: bool,
+}
+
+impl TryFrom<EmailFields> for Email {
+    type Error = &'static str;
+
+    fn try_from(fields: EmailFields) -> Result<Self, Self::Error> {
+        let EmailFields {
+            subject,
+            body,
+            from,
+            to,
+            headers,
+            html,
+        } = fields;
+
+        if [&subject, &body, &from, &to]
+            .iter()
+            .all(|field| field.is_empty())
+            && headers.is_empty()
+        {
+            return Err("email should have a body, a subject, a sender, a recipient or headers");
+        }
+
+        Ok(Self {
+            subject,
+            body,
+            from,
+            to,
+            headers,
+            html,
+        })
+    }
+}
+
+impl Email {
+    /// Email made of a body only, tokenized the same way as a plain text
+    #[allow(dead_code)]
+    pub fn from_body(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Email with its HTML body parsed once, for reading it several times
+    pub fn content(&self) -> EmailContent<'_> {
+        EmailContent {
+            email: self,
+            html: self.html.then(|| HtmlContent::parse(&self.body)),
+        }
+    }
+
+    /// Non-empty fields but the body, along with the key prefix of their tokens
+    fn fields(&self) -> impl Iterator<Item = (String, &str)> {
+        [
+            (SUBJECT_PREFIX.to_string(), self.subject.as_str()),
+            (FROM_PREFIX.to_string(), self.from.as_str()),
+            (TO_PREFIX.to_string(), self.to.as_str()),
+        ]
+        .into_iter()
+        .chain(self.headers.iter().map(|(name, value)| {
+            let prefix = format!("{HEADER_PREFIX}{}:", name.to_lowercase());
+            (prefix, value.as_str())
+        }))
+        .filter(|(_, value)| !value.is_empty())
+    }
+}
+
+/// An email along with the visible content of its HTML body
+///
+/// Each [`Document`] method of [`Email`] parses HTML bodies anew, while this
+/// one is parsed once.
+pub struct EmailContent<'a> {
+    email: &'a Email,
+    html: Option<HtmlContent>,
+}
+
+impl EmailContent<'_> {
+    /// Body as a reader sees it, the visible text of HTML bodies
+    fn body(&self) -> &str {
+        self.html
+            .as_ref()
+            .map_or(self.email.body.as_str(), |content| content.text.as_str())
+    }
+}
+
+/// Body tokens come first, tokenized as a plain text or an HTML document
+impl Document for EmailContent<'_> {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String> {
+        self.tokenize_with_offsets(tokenizer)
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    /// Offsets are relative to the field each token comes from, or to the
+    /// visible text of HTML bodies
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token> {
+        let mut tokens = match &self.html {
+            Some(content) => content.tokenize_with_offsets(tokenizer),
+            None => tokenizer.tokenize_with_offsets(&self.email.body),
+        };
+        tokens.extend(self.email.fields().flat_map(|(prefix, value)| {
+            tokenizer
+                .tokenize_with_offsets(value)
+                .into_iter()
+                .map(move |token| Token {
+                    text: format!("{prefix}{}", token.text),
+                    ..token
+                })
+        }));
+        tokens
+    }
+
+    /// The language is detected on the subject and the visible body
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str> {
+        tokenizer.detect_language(&format!("{}\n{}", self.email.subject, self.body()))
+    }
+
+    /// URLs of the body, then of the other fields
+    fn urls(&self) -> Vec<Url> {
+        let mut urls = match &self.html {
+            Some(content) => content.urls(),
+            None => url::extract(&self.email.body),
+        };
+        urls.extend(
+            self.email
+                .fields()
+                .flat_map(|(_, value)| url::extract(value)),
+        );
+        urls
+    }
+
+    /// The subject, sender and recipients, then the visible body, one per line
+    fn visible_text(&self) -> Cow<'_, str> {
+        let email = self.email;
+        let fields = [email.subject.as_str(), &email.from, &email.to, self.body()];
+        Cow::Owned(
+            fields
+                .into_iter()
+                .filter(|field| !field.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+}
+
+impl Document for Email {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String> {
+        self.content().tokenize(tokenizer)
+    }
+
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token> {
+        self.content().tokenize_with_offsets(tokenizer)
+    }
+
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str> {
+        self.content().detect_language(tokenizer)
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        self.content().urls()
+    }
+
+    fn visible_text(&self) -> Cow<'_, str> {
+        Cow::Owned(self.content().visible_text().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        serde_json::from_value(serde_json::json!({
+            "subject": "Free money",
+            "body": "Claim your free prize",
+            "from": "promo@example.com",
+            "headers": { "X-Mailer": "Bulk" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_field_prefixes() {
+        let tokenizer = TokenizerConfig::default();
+        let tokens = email().tokenize(&tokenizer);
+
+        assert!(tokens.contains(&"free".to_string()));
+        assert!(tokens.contains(&format!("{SUBJECT_PREFIX}free")));
+        // Sender domains are kept whole, next to the local part
+        assert!(tokens.contains(&format!("{FROM_PREFIX}promo")));
+        assert!(tokens.contains(&format!("{FROM_PREFIX}example.com")));
+        assert!(tokens.contains(&format!("{HEADER_PREFIX}x-mailer:bulk")));
+        assert!(!tokens.iter().any(|token| token.starts_with(TO_PREFIX)));
+
+        // Bodies alone are tokenized as plain texts
+        let body = "Claim your free prize";
+        assert_eq!(
+            Email::from_body(body).tokenize(&tokenizer),
+            body.tokenize(&tokenizer)
+        );
+    }
+
+    #[test]
+    fn test_offsets() {
+        let tokenizer = TokenizerConfig::default();
+        let email = email();
+
+        let tokens = email.tokenize_with_offsets(&tokenizer);
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| token.text.clone())
+                .collect::<Vec<_>>(),
+            email.tokenize(&tokenizer)
+        );
+
+        let subject = tokens
+            .iter()
+            .find(|token| token.text == format!("{SUBJECT_PREFIX}free"))
+            .unwrap();
+        assert_eq!((subject.start, subject.end), (0, 4));
+    }
+
+    #[test]
+    fn test_text_alias() {
+        let email: Email = serde_json::from_str(r#"{"text": "hello"}"#).unwrap();
+        assert_eq!(email, Email::from_body("hello"));
+    }
+
+    #[test]
+    fn test_invalid_emails() {
+        for json in [
+            r#"{}"#,
+            r#"{"txt": "hello"}"#,
+            r#"{"body": "", "html": true}"#,
+        ] {
+            assert!(serde_json::from_str::<Email>(json).is_err(), "{json}");
+        }
+        let email: Email = serde_json::from_str(r#"{"subject": "hello"}"#).unwrap();
+        assert_eq!(email.subject, "hello");
+    }
+
+    #[test]
+    fn test_html_body() {
+        let tokenizer = TokenizerConfig::default();
+        let email = Email {
+            body: r#"<p>Claim your <b>prize</b></p><div style="display:none">pills</div>"#.into(),
+            html: true,
+            ..email()
+        };
+
+        let tokens = email.tokenize(&tokenizer);
+        assert!(tokens.contains(&"prize".to_string()));
+        assert!(!tokens
+            .iter()
+            .any(|token| token == "div" || token.starts_with("pill")));
+        assert!(tokens.contains(&super::super::html::HtmlFeature::HiddenText.token()));
+        assert_eq!(email.tokenize_with_offsets(&tokenizer).len(), tokens.len());
+    }
+}
@@ -0,0 +1,181 @@
+//! Graham-style scoring on the most interesting tokens
+//!
+//! As described in "A Plan for Spam", only the distinct tokens whose spam
+//! probability is furthest from 0.5 are combined. Padding a message with
+//! innocent words then can't drown out its spammiest tokens.
+
+use std::collections::HashSet;
+
+use super::{Counter, NaiveBayesClassifier};
+
+/// Number of tokens combined by default
+pub const DEFAULT_INTERESTING_TOKENS: usize = 15;
+
+/// Probability of tokens seen too rarely to be trusted
+pub const UNKNOWN_TOKEN_PROBABILITY: f64 = 0.4;
+
+/// Minimum number of occurrences, ham ones counting twice, for a token to be trusted
+pub const MIN_OCCURRENCES: u32 = 5;
+
+/// A token selected for scoring along with its spam probability
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct InterestingToken {
+    pub token: String,
+    pub probability: f64,
+}
+
+impl<D: AsRef<[u8]>> NaiveBayesClassifier<D> {
+    /// Select the distinct tokens whose spam probability is furthest from 0.5
+    ///
+    /// At most `interesting_tokens` tokens are kept, from the most to the least
+    /// interesting.
+    pub fn interesting_tokens(&self, tokens: &[String]) -> Vec<InterestingToken> {
+        let mut seen = HashSet::new();
+        let mut interesting: Vec<_> = tokens
+            .iter()
+            .filter(|token| seen.insert(token.as_str()))
+            .map(|token| InterestingToken {
+                token: token.clone(),
+                probability: self.graham_probability(&self.get_token_counter(token)),
+            })
+            .collect();
+
+        interesting.sort_by(|left, right| {
+            let left_distance = (left.probability - 0.5).abs();
+            let right_distance = (right.probability - 0.5).abs();
+
+            right_distance
+                .total_cmp(&left_distance)
+                .then_with(|| left.token.cmp(&right.token))
+        });
+        interesting.truncate(self.interesting_tokens);
+        interesting
+    }
+
+    /// Combine the most interesting tokens
+    ///
+    /// Returns `(ln Πp, ln Π(1 - p))`, which normalize to Graham's
+    /// `Πp / (Πp + Π(1 - p))`.
+    pub(super) fn graham_log_probabilities(&self, tokens: &[String]) -> (f64, f64) {
+        self.interesting_tokens(tokens)
+            .iter()
+            .fold((0.0, 0.0), |(log_spam, log_ham), token| {
+                (
+                    log_spam + token.probability.ln(),
+                    log_ham + (1.0 - token.probability).ln(),
+                )
+            })
+    }
+
+    /// Calculate Graham's spam probability of a token
+    ///
+    /// Ham occurrences count twice to bias against false positives, and the
+    /// probability is clamped to [0.01, 0.99].
+    fn graham_probability(&self, counter: &Counter) -> f64 {
+        let good = 2 * counter.ham;
+        let bad = counter.spam;
+        if good + bad < MIN_OCCURRENCES {
+            return UNKNOWN_TOKEN_PROBABILITY;
+        }
+
+        let (spam_size, ham_size) = if self.stats.spam_documents + self.stats.ham_documents > 0 {
+            (self.stats.spam_documents, self.stats.ham_documents)
+        } else {
+            (self.stats.total_spam, self.stats.total_ham)
+        };
+        let bad_ratio = (bad as f64 / spam_size.max(1) as f64).min(1.0);
+        let good_ratio = (good as f64 / ham_size.max(1) as f64).min(1.0);
+
+        (bad_ratio / (good_ratio + bad_ratio)).clamp(0.01, 0.99)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::super::model::ModelHeader;
+    use super::super::ScoringMode;
+    use super::*;
+
+    fn build_model() -> Vec<u8> {
+        fixtures::build_model(
+            &[
+                ("free", Counter { spam: 40, ham: 0 }),
+                ("lunch", Counter { spam: 1, ham: 30 }),
+                ("money", Counter { spam: 30, ham: 2 }),
+                ("rare", Counter { spam: 2, ham: 0 }),
+                ("today", Counter { spam: 10, ham: 12 }),
+            ],
+            ModelHeader {
+                spam_documents: 50,
+                ham_documents: 50,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn classifier(data: &[u8], interesting_tokens: usize) -> NaiveBayesClassifier<&[u8]> {
+        let mut classifier = fixtures::classifier(data, ScoringMode::Graham);
+        classifier.set_interesting_tokens(interesting_tokens);
+        classifier
+    }
+
+    #[test]
+    fn test_graham_probability() {
+        let data = build_model();
+        let classifier = classifier(&data, DEFAULT_INTERESTING_TOKENS);
+
+        assert_eq!(
+            classifier.graham_probability(&Counter { spam: 2, ham: 0 }),
+            UNKNOWN_TOKEN_PROBABILITY
+        );
+        assert_eq!(
+            classifier.graham_probability(&Counter { spam: 40, ham: 0 }),
+            0.99
+        );
+        assert_eq!(
+            classifier.graham_probability(&Counter { spam: 0, ham: 30 }),
+            0.01
+        );
+
+        // Ham occurrences weigh twice as much
+        let balanced = classifier.graham_probability(&Counter { spam: 10, ham: 10 });
+        assert!((balanced - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_interesting_tokens() {
+        let data = build_model();
+        let classifier = classifier(&data, 2);
+
+        let tokens = classifier
+            .tokenizer()
+            .tokenize("today free lunch free money");
+        let interesting = classifier.interesting_tokens(&tokens);
+
+        // Repeats are deduplicated, neutral tokens left out
+        assert_eq!(
+            interesting
+                .iter()
+                .map(|token| token.token.as_str())
+                .collect::<Vec<_>>(),
+            ["free", "lunch"]
+        );
+    }
+
+    #[test]
+    fn test_resists_padding() {
+        let data = build_model();
+        let classifier = classifier(&data, 3);
+
+        let spam = classifier.classify("free money");
+        let padded = classifier.classify("free money today today today today lunch");
+        assert!(spam > 0.9);
+        assert!(padded > 0.9, "padded spam scored {padded}");
+
+        let result = classifier.classify_detailed("free money");
+        let interesting = result.interesting_tokens.unwrap();
+        assert_eq!(interesting.len(), 2);
+        assert_eq!(interesting[0].token, "free");
+    }
+}
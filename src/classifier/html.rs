@@ -0,0 +1,644 @@
+//! HTML-aware text extraction
+//!
+//! HTML inputs are reduced to the text a reader would see, so that tag names,
+//! attributes, scripts and styles stay out of the model. Tricks used to fool
+//! filters become features instead: text hidden from the reader, links whose
+//! text shows another address than their target, and messages made of images
+//! with hardly any text.
+
+use std::borrow::Cow;
+
+use super::tokenizer::{Document, Token, TokenizerConfig};
+use super::url::{self, Url};
+
+/// Key prefix of HTML features, which can't clash with alphanumeric tokens
+pub const HTML_FEATURE_PREFIX: &str = "html:";
+
+/// Messages with images and at most this many visible words are image-only
+pub const IMAGE_ONLY_MAX_WORDS: usize = 3;
+
+/// Font sizes below this many pixels count as hidden text
+const TINY_FONT_PIXELS: f64 = 3.0;
+
+/// Elements whose content is never rendered as text
+const NON_VISIBLE_ELEMENTS: &[&str] = &["head", "noscript", "script", "style", "template", "title"];
+
+/// Elements whose content is raw text, only ended by their closing tag
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Elements without content nor closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements breaking words apart, unlike inline elements such as `<b>` in `fr<b>ee</b>`
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "img",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// Spam signal found in the markup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HtmlFeature {
+    /// Text hidden with `display:none`, `visibility:hidden`, a zero opacity or a tiny font
+    HiddenText,
+    /// Link text showing another domain than the link target
+    LinkMismatch,
+    /// Images with hardly any visible text
+    ImageOnly,
+}
+
+impl HtmlFeature {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::HiddenText => "hidden_text",
+            Self::LinkMismatch => "link_mismatch",
+            Self::ImageOnly => "image_only",
+        }
+    }
+
+    /// Token standing for the feature in the model
+    pub fn token(self) -> String {
+        format!("{HTML_FEATURE_PREFIX}{}", self.name())
+    }
+}
+
+/// Visible text of an HTML document along with its spam signals
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HtmlContent {
+    pub text: String,
+    pub features: Vec<HtmlFeature>,
+    /// Link targets, which the visible text may not show
+    pub links: Vec<Url>,
+}
+
+/// Open element while walking the markup
+struct Element {
+    name: String,
+    /// Hidden from the reader by its style, a trick worth a feature
+    hidden: bool,
+    /// Never rendered, like scripts
+    non_visible: bool,
+    /// Target domain of a link, and where the link text starts
+    link: Option<(Option<String>, usize)>,
+}
+
+impl HtmlContent {
+    pub fn parse(html: &str) -> Self {
+        let mut parser = Parser::default();
+        parser.run(html);
+
+        let mut features = Vec::new();
+        if parser.hidden_text {
+            features.push(HtmlFeature::HiddenText);
+        }
+        if parser.link_mismatch {
+            features.push(HtmlFeature::LinkMismatch);
+        }
+        let words = parser
+            .text
+            .split_whitespace()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .count();
+        if parser.images > 0 && words <= IMAGE_ONLY_MAX_WORDS {
+            features.push(HtmlFeature::ImageOnly);
+        }
+
+        Self {
+            text: parser.text,
+            features,
+            links: parser.links,
+        }
+    }
+
+    /// Link targets not already found in the visible text
+    fn hidden_links(&self) -> Vec<Url> {
+        let shown = url::extract(&self.text);
+        self.links
+            .iter()
+            .filter(|link| !shown.iter().any(|url| url.url == link.url))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    text: String,
+    stack: Vec<Element>,
+    images: usize,
+    links: Vec<Url>,
+    hidden_text: bool,
+    link_mismatch: bool,
+}
+
+impl Parser {
+    fn run(&mut self, html: &str) {
+        let mut rest = html;
+
+        while !rest.is_empty() {
+            let Some(start) = rest.find('<') else {
+                self.push_text(rest);
+                break;
+            };
+            self.push_text(&rest[..start]);
+            rest = &rest[start..];
+
+            rest = if let Some(comment) = rest.strip_prefix("<!--") {
+                comment.find("-->").map_or("", |end| &comment[end + 3..])
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest.find('>').map_or("", |end| &rest[end + 1..])
+            } else if let Some(tag) = rest.strip_prefix("</") {
+                let end = tag.find('>').unwrap_or(tag.len());
+                self.close(&tag_name(&tag[..end]));
+                tag.get(end + 1..).unwrap_or_default()
+            } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                self.open(&rest[1..])
+            } else {
+                // A lone `<` is text
+                self.push_text("<");
+                &rest[1..]
+            };
+        }
+
+        while let Some(element) = self.stack.pop() {
+            self.end_element(element);
+        }
+    }
+
+    /// Handle an opening tag, returning the input following it
+    fn open<'a>(&mut self, input: &'a str) -> &'a str {
+        let end = tag_end(input);
+        let tag = &input[..end];
+        let rest = input.get(end + 1..).unwrap_or_default();
+
+        let name = tag_name(tag);
+        let attributes = attributes(&tag[name.len()..]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let parent_hidden = self.stack.last().is_some_and(|parent| parent.hidden);
+        let parent_non_visible = self.stack.last().is_some_and(|parent| parent.non_visible);
+        let hidden = parent_hidden
+            || attribute("hidden").is_some()
+            || attribute("style").is_some_and(is_hidden_style);
+        let non_visible = parent_non_visible || NON_VISIBLE_ELEMENTS.contains(&name.as_str());
+
+        if BLOCK_ELEMENTS.contains(&name.as_str()) {
+            self.text.push(' ');
+        }
+        if name == "img" && !hidden && !non_visible {
+            self.images += 1;
+        }
+
+        if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            let closing = format!("</{name}");
+            return match rest.to_ascii_lowercase().find(&closing) {
+                Some(position) => {
+                    let after = &rest[position..];
+                    after.find('>').map_or("", |end| &after[end + 1..])
+                }
+                None => "",
+            };
+        }
+
+        if VOID_ELEMENTS.contains(&name.as_str()) || tag.ends_with('/') {
+            return rest;
+        }
+
+        if name == "a" {
+            self.links.extend(attribute("href").and_then(Url::parse));
+        }
+        let link =
+            (name == "a").then(|| (attribute("href").and_then(href_domain), self.text.len()));
+        self.stack.push(Element {
+            name,
+            hidden,
+            non_visible,
+            link,
+        });
+        rest
+    }
+
+    /// Handle a closing tag, also closing the elements left open inside it
+    fn close(&mut self, name: &str) {
+        let Some(index) = self.stack.iter().rposition(|element| element.name == name) else {
+            return;
+        };
+
+        while self.stack.len() > index {
+            let element = self.stack.pop().unwrap();
+            self.end_element(element);
+        }
+
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.text.push(' ');
+        }
+    }
+
+    fn end_element(&mut self, element: Element) {
+        let Some((Some(target), start)) = element.link else {
+            return;
+        };
+
+        let shown = self.text[start..].trim();
+        if let Some(shown) = text_domain(shown) {
+            if !same_site(&shown, &target) {
+                self.link_mismatch = true;
+            }
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        match self.stack.last() {
+            Some(element) if element.non_visible => {}
+            Some(element) if element.hidden => {
+                if text.chars().any(char::is_alphanumeric) {
+                    self.hidden_text = true;
+                }
+            }
+            _ => self.text.push_str(&decode_entities(text)),
+        }
+    }
+}
+
+/// Position of the `>` ending a tag, skipping quoted attribute values
+fn tag_end(input: &str) -> usize {
+    let mut quote = None;
+    for (index, c) in input.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return index,
+            _ => {}
+        }
+    }
+    input.len()
+}
+
+/// Lowercased name at the start of a tag
+fn tag_name(tag: &str) -> String {
+    tag.chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Attributes of a tag, with lowercased names and decoded values
+fn attributes(input: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = input.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &after[1..];
+                    let end = inner.find(quote).unwrap_or(inner.len());
+                    (&inner[..end], inner.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = remaining;
+        }
+
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    }
+
+    attributes
+}
+
+/// Whether an inline style hides the text from the reader
+fn is_hidden_style(style: &str) -> bool {
+    let style: String = style
+        .to_ascii_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    style.split(';').any(|declaration| {
+        let Some((property, value)) = declaration.split_once(':') else {
+            return false;
+        };
+        let value = value.trim_end_matches("!important");
+
+        match property {
+            "display" => value == "none",
+            "visibility" => value == "hidden",
+            "opacity" => value.parse::<f64>().is_ok_and(|opacity| opacity == 0.0),
+            "font-size" => is_tiny_font(value),
+            _ => false,
+        }
+    })
+}
+
+fn is_tiny_font(value: &str) -> bool {
+    let number_end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let Ok(size) = value[..number_end].parse::<f64>() else {
+        return false;
+    };
+
+    let pixels = match &value[number_end..] {
+        "" | "px" => size,
+        "pt" => size * 4.0 / 3.0,
+        "em" | "rem" => size * 16.0,
+        "%" => size * 0.16,
+        _ => return false,
+    };
+    pixels < TINY_FONT_PIXELS
+}
+
+/// Lowercased domain of a web link, `None` for other schemes
+fn href_domain(href: &str) -> Option<String> {
+    let href = href.trim();
+    let lowercase = href.to_ascii_lowercase();
+    let rest = ["http://", "https://", "//"]
+        .iter()
+        .find_map(|scheme| lowercase.strip_prefix(scheme))?;
+    domain(rest)
+}
+
+/// Domain shown by a link text, when the text looks like an address
+fn text_domain(text: &str) -> Option<String> {
+    let lowercase = text.to_ascii_lowercase();
+    let rest = ["http://", "https://"]
+        .iter()
+        .find_map(|scheme| lowercase.strip_prefix(scheme))
+        .unwrap_or(&lowercase);
+    if rest.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let domain = domain(rest)?;
+    let tld = domain.rsplit('.').next().unwrap_or_default();
+    (domain.contains('.') && tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+        .then_some(domain)
+}
+
+/// Domain at the start of an address without scheme, without user info nor port
+fn domain(address: &str) -> Option<String> {
+    let authority = address.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let host = host.trim_end_matches('.');
+
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    valid.then(|| host.to_string())
+}
+
+/// Whether a link target belongs to the domain shown, or one of its subdomains
+fn same_site(shown: &str, target: &str) -> bool {
+    let shown = shown.strip_prefix("www.").unwrap_or(shown);
+    let target = target.strip_prefix("www.").unwrap_or(target);
+    target == shown || target.ends_with(&format!(".{shown}"))
+}
+
+/// Decode character references, leaving unknown ones as is
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map_or(rest.len(), |end| end + 1);
+        let name = &rest[1..end];
+        let character = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => name.strip_prefix('#').and_then(|code| {
+                let value = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code.parse().ok(),
+                };
+                value.and_then(char::from_u32)
+            }),
+        };
+
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end..];
+                rest = rest.strip_prefix(';').unwrap_or(rest);
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Link targets and features come after the text tokens, with empty offsets
+impl Document for HtmlContent {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String> {
+        self.tokenize_with_offsets(tokenizer)
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token> {
+        let mut tokens = tokenizer.tokenize_with_offsets(&self.text);
+        let links = if tokenizer.urls {
+            self.hidden_links()
+        } else {
+            Vec::new()
+        };
+
+        let extra = links
+            .iter()
+            .flat_map(Url::tokens)
+            .chain(self.features.iter().map(|feature| feature.token()));
+        tokens.extend(extra.map(|text| Token {
+            text,
+            start: 0,
+            end: 0,
+        }));
+        tokens
+    }
+
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str> {
+        tokenizer.detect_language(&self.text)
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        let mut urls = url::extract(&self.text);
+        urls.extend(self.hidden_links());
+        urls
+    }
+
+    fn visible_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(html: &str) -> Vec<String> {
+        HtmlContent::parse(html)
+            .text
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_visible_text() {
+        let html = r#"<html><head><title>Offer</title><style>p { color: red }</style></head>
+            <body><!-- comment --><p class="x">Win&nbsp;a <a href="https://example.com">prize</a> &amp; more</p>
+            <script>var x = "<p>hidden</p>";</script><div>fr<b>ee</b> &#x24;5 &lt;3</div></body></html>"#;
+
+        assert_eq!(
+            words(html),
+            ["Win", "a", "prize", "&", "more", "free", "$5", "<3"]
+        );
+        assert!(HtmlContent::parse(html).features.is_empty());
+    }
+
+    #[test]
+    fn test_hidden_text() {
+        for html in [
+            r#"<p>Hello</p><div style="display: none">cheap pills</div>"#,
+            r#"<p>Hello</p><span style="font-size:1px">cheap pills</span>"#,
+            r#"<p>Hello</p><span style="VISIBILITY:hidden !important">cheap pills</span>"#,
+            r#"<p>Hello</p><p hidden><b>cheap pills</b></p>"#,
+        ] {
+            let content = HtmlContent::parse(html);
+            assert_eq!(content.text.trim(), "Hello", "{html}");
+            assert_eq!(content.features, [HtmlFeature::HiddenText], "{html}");
+        }
+
+        // Small but readable fonts are fine
+        let content = HtmlContent::parse(r#"<span style="font-size: 10px">fine print</span>"#);
+        assert!(content.features.is_empty());
+    }
+
+    #[test]
+    fn test_link_mismatch() {
+        let mismatch = HtmlContent::parse(
+            r#"Log in at <a href="http://evil.example.ru/login">https://www.paypal.com</a>"#,
+        );
+        assert_eq!(mismatch.features, [HtmlFeature::LinkMismatch]);
+
+        for html in [
+            r#"<a href="https://mail.example.com/inbox">example.com</a>"#,
+            r#"<a href="https://www.example.com">Click here</a>"#,
+            r#"<a href="mailto:someone@example.com">other.org</a>"#,
+        ] {
+            assert!(HtmlContent::parse(html).features.is_empty(), "{html}");
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        let content = HtmlContent::parse(
+            r#"<a href="https://bit.ly/offer">Click here</a> or visit
+            <a href="https://example.com">https://example.com</a> <a href="/unsubscribe">stop</a>"#,
+        );
+        assert_eq!(content.links.len(), 2);
+
+        // Targets already in the visible text aren't counted twice
+        let urls: Vec<_> = content.urls().into_iter().map(|url| url.url).collect();
+        assert_eq!(urls, ["https://example.com", "https://bit.ly/offer"]);
+
+        let tokens = content.tokenize(&TokenizerConfig::latest());
+        assert!(tokens.contains(&url::SHORTENER_FEATURE.to_string()));
+    }
+
+    #[test]
+    fn test_image_only() {
+        let content = HtmlContent::parse(
+            r#"<a href="https://shop.example"><img src="offer.png"></a>Buy now"#,
+        );
+        assert_eq!(content.features, [HtmlFeature::ImageOnly]);
+
+        let content = HtmlContent::parse(
+            r#"<img src="logo.png"><p>Thanks for your order, it will ship tomorrow</p>"#,
+        );
+        assert!(content.features.is_empty());
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let content = HtmlContent::parse(r#"<p>Hello</p><div style="display:none">pills</div>"#);
+        let tokens = content.tokenize(&TokenizerConfig::latest());
+
+        assert!(!tokens
+            .iter()
+            .any(|token| token == "pill" || token == "pills"));
+        assert_eq!(tokens.last().unwrap(), &HtmlFeature::HiddenText.token());
+    }
+}
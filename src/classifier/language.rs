@@ -0,0 +1,164 @@
+//! Language-specific models
+//!
+//! A model can embed one sub-model per language, trained only on the documents
+//! detected in that language. Inputs are routed to the sub-model of their
+//! dominant language, falling back to the global model for other languages or
+//! when no language can be detected.
+
+use std::sync::LazyLock;
+
+use super::model::ModelFile;
+use super::{Document, NaiveBayesClassifier, MODEL};
+
+/// Router built from the embedded model on first use and shared by every request
+static SHARED_ROUTER: LazyLock<LanguageRouter<'static>> =
+    LazyLock::new(|| LanguageRouter::from_bytes(MODEL).unwrap());
+
+/// Global classifier along with its language-specific sub-models
+#[derive(Clone)]
+pub struct LanguageRouter<'a> {
+    global: NaiveBayesClassifier<&'a [u8]>,
+    languages: Vec<(String, NaiveBayesClassifier<&'a [u8]>)>,
+}
+
+impl LanguageRouter<'static> {
+    /// Get the process-wide router built from the embedded model
+    pub fn shared() -> &'static Self {
+        &SHARED_ROUTER
+    }
+}
+
+impl<'a> LanguageRouter<'a> {
+    /// Load the global model and every sub-model of a model container
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        let mut file = ModelFile::parse(data)?;
+
+        let languages = std::mem::take(&mut file.languages)
+            .into_iter()
+            .map(|(language, data)| Ok((language, NaiveBayesClassifier::from_bytes(data)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            global: NaiveBayesClassifier::from_file(file)?,
+            languages,
+        })
+    }
+
+    pub fn global(&self) -> &NaiveBayesClassifier<&'a [u8]> {
+        &self.global
+    }
+
+    /// Apply the same settings to the global classifier and every sub-model
+    pub fn configure(&mut self, mut configure: impl FnMut(&mut NaiveBayesClassifier<&'a [u8]>)) {
+        configure(&mut self.global);
+        for (_, classifier) in &mut self.languages {
+            configure(classifier);
+        }
+    }
+
+    /// Codes of the languages having a sub-model
+    #[allow(dead_code)]
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.languages.iter().map(|(language, _)| language.as_str())
+    }
+
+    /// Pick the classifier for the document
+    ///
+    /// Returns the language of the selected sub-model, or `None` along with the
+    /// global classifier. Models without sub-models skip language detection.
+    pub fn route(
+        &self,
+        document: &(impl Document + ?Sized),
+    ) -> (Option<&str>, &NaiveBayesClassifier<&'a [u8]>) {
+        if self.languages.is_empty() {
+            return (None, &self.global);
+        }
+
+        let Some(detected) = document.detect_language(self.global.tokenizer()) else {
+            return (None, &self.global);
+        };
+
+        match self
+            .languages
+            .iter()
+            .find(|(language, _)| language == detected)
+        {
+            Some((language, classifier)) => (Some(language), classifier),
+            None => (None, &self.global),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::model::{self, ModelHeader};
+    use super::*;
+
+    fn build_container(spam_documents: u32, languages: &[(String, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = fst::MapBuilder::memory();
+        builder.insert("hello", 1).unwrap();
+        let fst = builder.into_inner().unwrap();
+
+        let header = ModelHeader {
+            spam_documents,
+            ham_documents: 1,
+            vocabulary_size: 1,
+            ..Default::default()
+        };
+
+        let mut data = Vec::new();
+        let sections = model::ModelSections {
+            languages,
+            ..model::ModelSections::new(&header, &fst)
+        };
+        model::write(&mut data, sections).unwrap();
+        data
+    }
+
+    fn router(data: &[u8]) -> LanguageRouter<'_> {
+        LanguageRouter::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn test_route_by_language() {
+        let data = build_container(1, &[("fra".to_string(), build_container(2, &[]))]);
+        let router = router(&data);
+        assert_eq!(router.languages().collect::<Vec<_>>(), ["fra"]);
+
+        let (language, classifier) =
+            router.route("Bonjour, je voudrais réserver une table pour deux personnes ce soir");
+        assert_eq!(language, Some("fra"));
+        assert_eq!(classifier.stats().spam_documents, 2);
+
+        // Languages without a sub-model use the global model
+        let (language, classifier) =
+            router.route("Hello, I would like to book a table for two people tonight");
+        assert_eq!(language, None);
+        assert_eq!(classifier.stats().spam_documents, 1);
+
+        assert_eq!(router.route("").0, None);
+    }
+
+    #[test]
+    fn test_route_without_languages() {
+        let data = build_container(1, &[]);
+        let router = router(&data);
+
+        let (language, classifier) =
+            router.route("Bonjour, je voudrais réserver une table pour deux personnes ce soir");
+        assert_eq!(language, None);
+        assert_eq!(classifier.stats().spam_documents, 1);
+    }
+
+    #[test]
+    fn test_configure() {
+        let data = build_container(1, &[("fra".to_string(), build_container(2, &[]))]);
+        let mut router = router(&data);
+        router.configure(|classifier| classifier.set_spam_threshold(0.5));
+
+        assert_eq!(router.global().spam_threshold(), 0.5);
+        let (_, classifier) =
+            router.route("Bonjour, je voudrais réserver une table pour deux personnes ce soir");
+        assert_eq!(classifier.spam_threshold(), 0.5);
+    }
+}
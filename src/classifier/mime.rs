@@ -0,0 +1,491 @@
+//! Raw RFC 5322 / MIME messages
+//!
+//! Messages are turned into an [`Email`]: the subject, sender and recipients
+//! come from the headers, a few other headers are kept as is, and the body is the
+//! first text/plain part, or else the first text/html part, marked as HTML.
+//! Quoted-printable and base64 transfer encodings are decoded, as well as
+//! RFC 2047 encoded words in headers.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use super::email::Email;
+
+/// Headers kept along with the subject, sender and recipients
+///
+/// Trace and signature headers such as `Received`, `Message-ID`, `Date` or
+/// `DKIM-Signature` are left out: their values are unique to each message,
+/// and would fill the model with tokens seen only once.
+const KEPT_HEADERS: &[&str] = &[
+    "reply-to",
+    "return-path",
+    "x-mailer",
+    "user-agent",
+    "list-unsubscribe",
+    "list-id",
+    "precedence",
+];
+
+/// Maximum depth of nested multipart entities
+const MAX_DEPTH: usize = 8;
+
+impl TryFrom<&[u8]> for Email {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        parse(data)
+    }
+}
+
+/// Parse a raw message into an email
+pub fn parse(data: &[u8]) -> Result<Email> {
+    let (headers, body) = split_entity(data);
+    if headers.is_empty() {
+        anyhow::bail!("Message has no headers");
+    }
+
+    let mut email = Email::default();
+    for (name, value) in &headers {
+        match name.as_str() {
+            "subject" => email.subject = value.clone(),
+            "from" => email.from = value.clone(),
+            "to" => email.to = value.clone(),
+            _ if KEPT_HEADERS.contains(&name.as_str()) => {
+                // Repeated headers are joined
+                let joined = email.headers.entry(name.clone()).or_default();
+                if !joined.is_empty() {
+                    joined.push('\n');
+                }
+                joined.push_str(value);
+            }
+            _ => {}
+        }
+    }
+
+    let mut parts = TextParts::default();
+    collect_parts(&headers, body, &mut parts, 0);
+    match (parts.plain, parts.html) {
+        (Some(plain), _) => email.body = plain,
+        (None, Some(html)) => {
+            email.body = html;
+            email.html = true;
+        }
+        (None, None) => {}
+    }
+
+    Ok(email)
+}
+
+/// First text parts of a message
+#[derive(Default)]
+struct TextParts {
+    plain: Option<String>,
+    html: Option<String>,
+}
+
+/// Walk the entities of a message, keeping the first text/plain and text/html ones
+fn collect_parts(headers: &Headers, body: &[u8], parts: &mut TextParts, depth: usize) {
+    let content_type = ContentType::parse(header(headers, "content-type").unwrap_or_default());
+
+    let is_attachment = header(headers, "content-disposition")
+        .is_some_and(|value| value.trim_start().to_lowercase().starts_with("attachment"));
+    if is_attachment {
+        return;
+    }
+
+    if content_type.mime_type.starts_with("multipart/") {
+        let Some(boundary) = content_type.parameter("boundary") else {
+            return;
+        };
+        if depth >= MAX_DEPTH {
+            return;
+        }
+
+        for part in split_multipart(body, boundary) {
+            let (part_headers, part_body) = split_entity(part);
+            collect_parts(&part_headers, part_body, parts, depth + 1);
+        }
+        return;
+    }
+
+    let slot = match content_type.mime_type.as_str() {
+        "text/plain" => &mut parts.plain,
+        "text/html" => &mut parts.html,
+        _ => return,
+    };
+    if slot.is_some() {
+        return;
+    }
+
+    let encoding = header(headers, "content-transfer-encoding")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    let decoded = match encoding.as_str() {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    };
+
+    *slot = Some(decode_charset(
+        &decoded,
+        content_type.parameter("charset").unwrap_or("utf-8"),
+    ));
+}
+
+/// Unfolded headers, with lowercased names and decoded values, in order
+type Headers = Vec<(String, String)>;
+
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Split an entity into its headers and its body
+fn split_entity(data: &[u8]) -> (Headers, &[u8]) {
+    let mut headers: Headers = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .unwrap_or(rest.len());
+        let line = rest[..end].strip_suffix(b"\r").unwrap_or(&rest[..end]);
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        // An empty line ends the headers
+        if line.is_empty() {
+            break;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with([' ', '\t']) {
+            // Folded continuation of the previous header
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    for (_, value) in headers.iter_mut() {
+        *value = decode_encoded_words(value);
+    }
+
+    (headers, rest)
+}
+
+/// Parsed `Content-Type` header
+struct ContentType {
+    /// Lowercased `type/subtype`, text/plain by default
+    mime_type: String,
+    /// Parameters with lowercased names
+    parameters: BTreeMap<String, String>,
+}
+
+impl ContentType {
+    fn parse(value: &str) -> Self {
+        let mut fields = value.split(';');
+        let mime_type = fields
+            .next()
+            .map(|mime_type| mime_type.trim().to_lowercase())
+            .filter(|mime_type| !mime_type.is_empty())
+            .unwrap_or_else(|| "text/plain".to_string());
+
+        let parameters = fields
+            .filter_map(|parameter| parameter.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim().trim_matches('"');
+                (name.trim().to_lowercase(), value.to_string())
+            })
+            .collect();
+
+        Self {
+            mime_type,
+            parameters,
+        }
+    }
+
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+}
+
+/// Split a multipart body into its parts, leaving out the preamble and epilogue
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+
+    while offset < body.len() {
+        let end = body[offset..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(body.len(), |position| offset + position + 1);
+        let line = body[offset..end].trim_ascii_end();
+
+        if let Some(suffix) = line.strip_prefix(delimiter.as_bytes()) {
+            if let Some(start) = start {
+                // The line break before a delimiter belongs to the delimiter
+                let part = &body[start..offset];
+                let part = part.strip_suffix(b"\n").unwrap_or(part);
+                parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+            }
+            if suffix.starts_with(b"--") {
+                return parts;
+            }
+            start = Some(end);
+        }
+
+        offset = end;
+    }
+
+    // Unterminated multipart, keep the last part
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+
+    for byte in data {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            // Line breaks and other garbage are skipped
+            _ => continue,
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    decoded
+}
+
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut index = 0;
+
+    while index < data.len() {
+        if data[index] != b'=' {
+            decoded.push(data[index]);
+            index += 1;
+            continue;
+        }
+
+        let rest = &data[index + 1..];
+        if let Some(value) = rest.get(..2).and_then(decode_hex) {
+            decoded.push(value);
+            index += 3;
+        } else if rest.starts_with(b"\r\n") {
+            // Soft line break
+            index += 3;
+        } else if rest.starts_with(b"\n") {
+            index += 2;
+        } else {
+            decoded.push(b'=');
+            index += 1;
+        }
+    }
+
+    decoded
+}
+
+fn decode_hex(digits: &[u8]) -> Option<u8> {
+    let digits = std::str::from_utf8(digits).ok()?;
+    u8::from_str_radix(digits, 16).ok()
+}
+
+/// Decode text in a charset, Latin-1 compatible charsets being decoded byte per byte
+fn decode_charset(data: &[u8], charset: &str) -> String {
+    match charset.to_lowercase().as_str() {
+        "iso-8859-1" | "iso-8859-15" | "latin1" | "windows-1252" => {
+            data.iter().map(|byte| *byte as char).collect()
+        }
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+/// Decode the RFC 2047 encoded words of a header value, like `=?utf-8?B?...?=`
+fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut previous_was_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let Some((word, length)) = decode_encoded_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            previous_was_word = false;
+            continue;
+        };
+
+        // Whitespace between adjacent encoded words is dropped
+        let between = &rest[..start];
+        if !(previous_was_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&word);
+        rest = &rest[start + length..];
+        previous_was_word = true;
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decode an encoded word at the start of the input, along with its length
+fn decode_encoded_word(input: &str) -> Option<(String, usize)> {
+    let inner = input.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let text = &inner[..end];
+
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(text.as_bytes()),
+        "Q" | "q" => decode_quoted_printable(text.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+
+    // Charsets may carry a language, as in `utf-8*en`
+    let charset = charset.split('*').next().unwrap_or_default();
+    let length = input.len() - inner.len() + end + 2;
+
+    Some((decode_charset(&bytes, charset), length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(lines: &[&str]) -> Result<Email> {
+        parse(lines.join("\r\n").as_bytes())
+    }
+
+    #[test]
+    fn test_simple_message() {
+        let email = message(&[
+            "From: Winner <winner@example.com>",
+            "To: you@example.com",
+            "Subject: FREE",
+            " MONEY",
+            "X-Mailer: Bulk",
+            "Reply-To: claims@example.net",
+            "Received: from a",
+            "Received: from b",
+            "Message-ID: <1234@example.com>",
+            "DKIM-Signature: v=1; a=rsa-sha256; b=dGVzdA==",
+            "",
+            "Click here to win",
+        ])
+        .unwrap();
+
+        assert_eq!(email.subject, "FREE MONEY");
+        assert_eq!(email.from, "Winner <winner@example.com>");
+        assert_eq!(email.to, "you@example.com");
+        assert_eq!(email.body, "Click here to win");
+        // Trace and signature headers are left out
+        assert_eq!(
+            email.headers,
+            BTreeMap::from([
+                ("reply-to".to_string(), "claims@example.net".to_string()),
+                ("x-mailer".to_string(), "Bulk".to_string()),
+            ])
+        );
+
+        assert!(parse(b"").is_err());
+    }
+
+    #[test]
+    fn test_transfer_encodings() {
+        let email = message(&[
+            "Subject: =?utf-8?B?R3LDvMOfZQ==?= =?iso-8859-1?Q?_aus_M=FCnchen?=",
+            "Content-Type: text/plain; charset=utf-8",
+            "Content-Transfer-Encoding: quoted-printable",
+            "",
+            "Caf=C3=A9 au lait, soft=",
+            "break",
+        ])
+        .unwrap();
+
+        assert_eq!(email.subject, "Grüße aus München");
+        assert_eq!(email.body, "Café au lait, softbreak");
+
+        let email = message(&[
+            "Subject: Base64",
+            "Content-Transfer-Encoding: base64",
+            "",
+            "RlJFRSBN",
+            "T05FWQ==",
+        ])
+        .unwrap();
+        assert_eq!(email.body, "FREE MONEY");
+    }
+
+    #[test]
+    fn test_multipart() {
+        let email = message(&[
+            "Subject: Offer",
+            "Content-Type: multipart/mixed; boundary=\"outer\"",
+            "",
+            "Preamble",
+            "--outer",
+            "Content-Type: multipart/alternative; boundary=inner",
+            "",
+            "--inner",
+            "Content-Type: text/html",
+            "",
+            "<p>HTML <b>version</b></p>",
+            "--inner",
+            "Content-Type: text/plain",
+            "",
+            "Plain version",
+            "--inner--",
+            "--outer",
+            "Content-Type: text/plain",
+            "Content-Disposition: attachment; filename=notes.txt",
+            "",
+            "Attached notes",
+            "--outer--",
+        ])
+        .unwrap();
+
+        // Plain text is preferred over HTML, attachments are skipped
+        assert_eq!(email.body, "Plain version");
+        assert!(!email.html);
+        assert!(!email.headers.contains_key("content-type"));
+    }
+
+    #[test]
+    fn test_html_only() {
+        let email = message(&[
+            "Content-Type: text/html; charset=\"utf-8\"",
+            "",
+            "<html><style>p { color: red }</style><p>Win&nbsp;a <a href=\"#\">prize</a> &amp; more</p></html>",
+        ])
+        .unwrap();
+
+        assert!(email.html);
+        assert!(email.body.starts_with("<html>"));
+    }
+}
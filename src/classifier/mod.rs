@@ -0,0 +1,1269 @@
+pub const SPAM_TRESHOLD: f64 = 0.80;
+pub const DEFAULT_ALPHA: f64 = 1.0;
+pub const DEFAULT_EXPLAIN_TOP_N: usize = 5;
+
+pub const LABEL_SPAM: &str = "spam";
+pub const LABEL_HAM: &str = "ham";
+
+static MODEL: &[u8] = include_bytes!("../../model.fst");
+
+use std::collections::HashMap;
+
+use fst::Streamer;
+
+use calibration::Calibration;
+use chi_squared::ChiSquaredConfig;
+use decision::{
+    shift_log_odds, DecisionThresholds, ExpectedCosts, MisclassificationCosts, Verdict,
+};
+use domains::{ListAction, ListMatch};
+use graham::{InterestingToken, DEFAULT_INTERESTING_TOKENS};
+use language::LanguageRouter;
+use model::{ModelFile, ModelHeader};
+use multiclass::{LabelProbability, LabelTable};
+use rules::RuleHit;
+pub use scoring::ScoringMode;
+pub use tokenizer::{Document, TokenizerConfig};
+
+pub mod calibration;
+pub mod chi_squared;
+pub mod decision;
+pub mod domains;
+pub mod email;
+pub mod graham;
+pub mod html;
+pub mod language;
+pub mod mime;
+pub mod model;
+pub mod multiclass;
+pub mod obfuscation;
+pub mod placeholders;
+pub mod rules;
+pub mod scoring;
+pub mod style;
+pub mod tokenizer;
+pub mod url;
+
+#[derive(Default, Debug, Clone, Copy, serde::Serialize)]
+pub struct Counter {
+    pub spam: u32,
+    pub ham: u32,
+}
+
+impl Counter {
+    pub fn from_u64(value: u64) -> Self {
+        let spam = (value >> 32) as u32;
+        let ham = (value & u32::MAX as u64) as u32;
+
+        Self { spam, ham }
+    }
+
+    #[allow(dead_code)]
+    pub fn to_u64(self) -> u64 {
+        let spam = self.spam as u64;
+        let ham = self.ham as u64;
+
+        (spam << 32) | ham
+    }
+}
+
+/// Naive Bayes classifier statistics
+#[derive(Debug, Clone)]
+pub struct ClassifierStats {
+    pub total_spam: u32,
+    pub total_ham: u32,
+    pub total_tokens: u32,
+    pub unique_tokens: u32,
+    pub spam_documents: u32,
+    pub ham_documents: u32,
+}
+
+impl ClassifierStats {
+    pub fn new() -> Self {
+        Self {
+            total_spam: 0,
+            total_ham: 0,
+            total_tokens: 0,
+            unique_tokens: 0,
+            spam_documents: 0,
+            ham_documents: 0,
+        }
+    }
+
+    pub fn from_header(header: &ModelHeader) -> Self {
+        Self {
+            total_spam: header.spam_tokens,
+            total_ham: header.ham_tokens,
+            total_tokens: header.spam_tokens + header.ham_tokens,
+            unique_tokens: header.vocabulary_size,
+            spam_documents: header.spam_documents,
+            ham_documents: header.ham_documents,
+        }
+    }
+
+    pub fn from_model<D: AsRef<[u8]>>(model: &fst::Map<D>) -> Self {
+        let mut stats = Self::new();
+        let mut stream = model.stream();
+
+        while let Some((_, value)) = stream.next() {
+            let counter = Counter::from_u64(value);
+            stats.total_spam += counter.spam;
+            stats.total_ham += counter.ham;
+            stats.unique_tokens += 1;
+        }
+
+        stats.total_tokens = stats.total_spam + stats.total_ham;
+        stats
+    }
+
+    /// Calculate prior probability P(spam)
+    ///
+    /// Based on document counts when the model records them, and on token
+    /// counts for legacy models that don't.
+    pub fn prior_spam(&self) -> f64 {
+        let total_documents = self.spam_documents + self.ham_documents;
+        if total_documents > 0 {
+            return self.spam_documents as f64 / total_documents as f64;
+        }
+
+        if self.total_tokens == 0 {
+            return 0.5; // Default to 50% if no data
+        }
+        self.total_spam as f64 / self.total_tokens as f64
+    }
+
+    /// Calculate prior probability P(ham)
+    pub fn prior_ham(&self) -> f64 {
+        1.0 - self.prior_spam()
+    }
+}
+
+/// Optimized Naive Bayes classifier for spam detection
+#[derive(Clone)]
+pub struct NaiveBayesClassifier<D> {
+    model: fst::Map<D>,
+    stats: ClassifierStats,
+    labels: Option<LabelTable<D>>, // Counts table of multi-class models
+    tokenizer: TokenizerConfig,    // Tokenizer settings the model was trained with
+    calibration: Option<Calibration>, // Map from log-odds to calibrated probabilities
+    calibration_alpha: f64,        // Smoothing factor the calibration map was fitted with
+    mode: ScoringMode,             // Algorithm turning token counters into class scores
+    chi_squared: ChiSquaredConfig, // Parameters of the chi-squared scoring mode
+    interesting_tokens: usize,     // Number of tokens combined by the Graham scoring mode
+    alpha: f64,                    // Laplace smoothing parameter
+    spam_threshold: f64,           // Spam classification threshold
+    decision: DecisionThresholds,  // Thresholds of the ham/needs review/spam verdict
+    costs: Option<MisclassificationCosts>, // Costs deciding is_spam instead of the threshold
+    spam_denominator: f64,         // Likelihood denominator for spam, derived from stats and alpha
+    ham_denominator: f64,          // Likelihood denominator for ham, derived from stats and alpha
+    bernoulli_totals: (f64, f64),  // Token counts summed with each capped as in the Bernoulli mode
+}
+
+impl NaiveBayesClassifier<&'static [u8]> {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::from_bytes(MODEL).unwrap()
+    }
+
+    /// Get the process-wide classifier built from the embedded model
+    ///
+    /// The model is parsed and its statistics computed only once. Cloning the
+    /// shared classifier is cheap, so per-request settings should be applied
+    /// on a clone rather than by building a new classifier.
+    ///
+    /// This is the global model, [`LanguageRouter`] picks language sub-models.
+    #[allow(dead_code)]
+    pub fn shared() -> &'static Self {
+        LanguageRouter::shared().global()
+    }
+}
+
+impl<'a> NaiveBayesClassifier<&'a [u8]> {
+    /// Load a classifier from a model container or a legacy bare FST
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        Self::from_file(ModelFile::parse(data)?)
+    }
+
+    /// Load a classifier from a parsed model file, ignoring its language sub-models
+    pub fn from_file(file: ModelFile<'a>) -> anyhow::Result<Self> {
+        let model = fst::Map::new(file.fst)?;
+
+        let Some(header) = file.header else {
+            model::check_baseline_tokenizer()?;
+            return Ok(Self::from_model(model));
+        };
+        header.check_tokenizer()?;
+
+        let mut classifier = Self::from_parts(model, ClassifierStats::from_header(&header));
+        classifier.tokenizer = header.tokenizer;
+        classifier.calibration = header.calibration;
+        classifier.calibration_alpha = header.calibration_alpha.unwrap_or(DEFAULT_ALPHA);
+        if !header.labels.is_empty() {
+            classifier.labels = Some(LabelTable::new(
+                header.labels,
+                &header.ham_label,
+                file.counts,
+            ));
+        }
+        classifier.bernoulli_totals = classifier.capped_totals();
+
+        Ok(classifier)
+    }
+}
+
+impl<D: AsRef<[u8]>> NaiveBayesClassifier<D> {
+    /// Build a classifier from a bare FST, computing its statistics by streaming every key
+    pub fn from_model(model: fst::Map<D>) -> Self {
+        let stats = ClassifierStats::from_model(&model);
+        Self::from_parts(model, stats)
+    }
+
+    /// Build a classifier from an FST and precomputed statistics
+    pub fn from_parts(model: fst::Map<D>, stats: ClassifierStats) -> Self {
+        // Counts only reach the caps of the Bernoulli mode with document counts,
+        // for which the totals are summed by streaming the model
+        let bernoulli_totals = (stats.total_spam as f64, stats.total_ham as f64);
+        let mut classifier = Self {
+            model,
+            stats,
+            labels: None,
+            tokenizer: TokenizerConfig::default(),
+            calibration: None,
+            calibration_alpha: DEFAULT_ALPHA,
+            mode: ScoringMode::default(),
+            chi_squared: ChiSquaredConfig::default(),
+            interesting_tokens: DEFAULT_INTERESTING_TOKENS,
+            alpha: DEFAULT_ALPHA,
+            spam_threshold: SPAM_TRESHOLD,
+            decision: DecisionThresholds::single(SPAM_TRESHOLD),
+            costs: None,
+            spam_denominator: 0.0,
+            ham_denominator: 0.0,
+            bernoulli_totals,
+        };
+        classifier.update_denominators();
+        classifier
+    }
+
+    /// Recompute the likelihood denominators after stats or alpha changed
+    fn update_denominators(&mut self) {
+        let smoothing = self.alpha * self.stats.unique_tokens as f64;
+        self.spam_denominator = self.stats.total_spam as f64 + smoothing;
+        self.ham_denominator = self.stats.total_ham as f64 + smoothing;
+    }
+
+    /// Calculate both spam and ham likelihoods from a single counter
+    fn calculate_likelihoods(&self, counter: &Counter) -> (f64, f64) {
+        let spam_likelihood = (counter.spam as f64 + self.alpha) / self.spam_denominator;
+        let ham_likelihood = (counter.ham as f64 + self.alpha) / self.ham_denominator;
+
+        (spam_likelihood, ham_likelihood)
+    }
+
+    /// Get token counter from the FST model
+    fn get_token_counter(&self, word: &str) -> Counter {
+        let Some(value) = self.model.get(word) else {
+            return Counter::default();
+        };
+
+        match &self.labels {
+            Some(labels) => labels.counter(value),
+            None => Counter::from_u64(value),
+        }
+    }
+
+    /// Classify text and return spam probability
+    pub fn classify(&self, document: &(impl Document + ?Sized)) -> f64 {
+        self.classify_tokens(&document.tokenize(&self.tokenizer))
+    }
+
+    fn classify_tokens(&self, tokens: &[String]) -> f64 {
+        let (log_prob_spam, log_prob_ham) = self.log_probabilities(tokens);
+
+        let result = match self.active_calibration() {
+            Some(calibration) => calibration.apply(log_prob_spam - log_prob_ham),
+            None => {
+                // Convert back to probability using Bayes' theorem, normalizing in log
+                // space so that long inputs don't underflow
+                let log_denominator = log_sum_exp(log_prob_spam, log_prob_ham);
+                (log_prob_spam - log_denominator).exp()
+            }
+        };
+
+        // Only reachable with a zero smoothing factor and unseen tokens
+        if result.is_nan() {
+            self.stats.prior_spam()
+        } else {
+            result
+        }
+    }
+
+    /// Classify text and return the log-likelihood ratio of spam over ham
+    ///
+    /// The class priors are included, so positive values lean towards spam and
+    /// negative values towards ham. Unlike the probability, the ratio keeps its
+    /// resolution for very long inputs.
+    #[allow(dead_code)]
+    pub fn classify_log_odds(&self, document: &(impl Document + ?Sized)) -> f64 {
+        let tokens = document.tokenize(&self.tokenizer);
+        let (log_prob_spam, log_prob_ham) = self.log_probabilities(&tokens);
+        log_prob_spam - log_prob_ham
+    }
+
+    /// Calculate the joint log probabilities of the tokens with each class, according to the scoring mode
+    fn log_probabilities(&self, tokens: &[String]) -> (f64, f64) {
+        match self.mode {
+            ScoringMode::Multinomial => self.multinomial_log_probabilities(tokens),
+            ScoringMode::Complement => self.complement_log_probabilities(tokens),
+            ScoringMode::Bernoulli => self.bernoulli_log_probabilities(tokens),
+            ScoringMode::Graham => self.graham_log_probabilities(tokens),
+            ScoringMode::ChiSquared => {
+                let probability = self.chi_squared_score(tokens).probability;
+                (probability.ln(), (1.0 - probability).ln())
+            }
+        }
+    }
+
+    fn multinomial_log_probabilities(&self, tokens: &[String]) -> (f64, f64) {
+        // Calculate log probabilities to avoid numerical underflow
+        let mut log_prob_spam = self.stats.prior_spam().ln();
+        let mut log_prob_ham = self.stats.prior_ham().ln();
+
+        for token in tokens {
+            let counter = self.get_token_counter(token);
+            let (p_word_spam, p_word_ham) = self.calculate_likelihoods(&counter);
+
+            // Add log probabilities instead of multiplying
+            log_prob_spam += p_word_spam.ln();
+            log_prob_ham += p_word_ham.ln();
+        }
+
+        (log_prob_spam, log_prob_ham)
+    }
+
+    /// Get the tokenizer settings of the model
+    #[allow(dead_code)]
+    pub fn tokenizer(&self) -> &TokenizerConfig {
+        &self.tokenizer
+    }
+
+    /// Get the model statistics
+    #[allow(dead_code)]
+    pub fn stats(&self) -> &ClassifierStats {
+        &self.stats
+    }
+
+    /// Set the alpha value for Laplace smoothing
+    #[allow(dead_code)]
+    pub fn set_alpha(&mut self, alpha: f64) {
+        self.alpha = alpha;
+        self.update_denominators();
+    }
+
+    /// Get the current alpha value
+    #[allow(dead_code)]
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// Get the calibration map matching the current settings
+    ///
+    /// Calibration maps are fitted on multinomial scores with the smoothing
+    /// factor recorded next to them, and don't fit scores computed otherwise.
+    fn active_calibration(&self) -> Option<&Calibration> {
+        self.calibration.as_ref().filter(|_| {
+            self.mode == ScoringMode::Multinomial && self.alpha == self.calibration_alpha
+        })
+    }
+
+    /// Get the calibration map of the model, if it was trained with one
+    #[allow(dead_code)]
+    pub fn calibration(&self) -> Option<&Calibration> {
+        self.calibration.as_ref()
+    }
+
+    /// Set the scoring algorithm
+    #[allow(dead_code)]
+    pub fn set_mode(&mut self, mode: ScoringMode) {
+        self.mode = mode;
+    }
+
+    /// Get the current scoring algorithm
+    #[allow(dead_code)]
+    pub fn mode(&self) -> ScoringMode {
+        self.mode
+    }
+
+    /// Set the parameters of the chi-squared scoring mode
+    #[allow(dead_code)]
+    pub fn set_chi_squared(&mut self, config: ChiSquaredConfig) {
+        self.chi_squared = config;
+    }
+
+    /// Set the number of tokens combined by the Graham scoring mode
+    #[allow(dead_code)]
+    pub fn set_interesting_tokens(&mut self, interesting_tokens: usize) {
+        self.interesting_tokens = interesting_tokens;
+    }
+
+    /// Set the spam threshold value
+    #[allow(dead_code)]
+    pub fn set_spam_threshold(&mut self, threshold: f64) {
+        self.spam_threshold = threshold;
+    }
+
+    /// Set the thresholds of the ham/needs review/spam verdict
+    #[allow(dead_code)]
+    pub fn set_decision_thresholds(&mut self, decision: DecisionThresholds) {
+        self.decision = decision;
+    }
+
+    /// Set misclassification costs, `is_spam` then minimizes the expected cost
+    #[allow(dead_code)]
+    pub fn set_costs(&mut self, costs: Option<MisclassificationCosts>) {
+        self.costs = costs;
+    }
+
+    /// Get the current spam threshold value
+    #[allow(dead_code)]
+    pub fn spam_threshold(&self) -> f64 {
+        self.spam_threshold
+    }
+
+    /// Get detailed classification results
+    pub fn classify_detailed(&self, document: &(impl Document + ?Sized)) -> ClassificationResult {
+        self.build_result(&document.tokenize(&self.tokenizer))
+    }
+
+    /// Get detailed classification results along with per-token contributions
+    ///
+    /// `top_n` bounds the number of distinct tokens listed as the strongest spam
+    /// and ham contributors.
+    pub fn classify_explained(
+        &self,
+        document: &(impl Document + ?Sized),
+        top_n: usize,
+    ) -> ClassificationResult {
+        let tokens = document.tokenize_with_offsets(&self.tokenizer);
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.clone()).collect();
+
+        // Contributions of repeated tokens are shared among their occurrences
+        let log_odds = self.token_log_odds(&texts);
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+        for text in &texts {
+            *occurrences.entry(text).or_default() += 1;
+        }
+
+        let contributions = tokens
+            .iter()
+            .map(|token| {
+                let text = token.text.as_str();
+                TokenContribution {
+                    token: token.text.clone(),
+                    start: token.start,
+                    end: token.end,
+                    counter: self.get_token_counter(text),
+                    log_likelihood_ratio: log_odds[text] / occurrences[text] as f64,
+                }
+            })
+            .collect();
+
+        let mut result = self.build_result(&texts);
+        result.explanation = Some(Explanation::new(contributions, top_n));
+        result
+    }
+
+    /// Adjust a result to the domain list entries matched by its input
+    pub fn apply_domain_lists(
+        &self,
+        result: ClassificationResult,
+        matches: &[ListMatch],
+        action: ListAction,
+    ) -> ClassificationResult {
+        match action.apply(result.spam_probability, matches) {
+            Some(spam_probability) => self.redecide(result, spam_probability),
+            None => result,
+        }
+    }
+
+    /// Adjust a result to the operator rules matched by its input
+    ///
+    /// Score adjustments shift the log-odds first. The first verdict forced by
+    /// a rule then replaces the verdict, and sets `is_spam` unless it is
+    /// `needs_review`.
+    pub fn apply_rules(
+        &self,
+        result: ClassificationResult,
+        hits: &[RuleHit],
+    ) -> ClassificationResult {
+        let score = rules::total_score(hits);
+        let mut result = if score != 0.0 {
+            let spam_probability = shift_log_odds(result.spam_probability, score);
+            self.redecide(result, spam_probability)
+        } else {
+            result
+        };
+
+        if let Some(verdict) = rules::forced_verdict(hits) {
+            result.verdict = verdict;
+            result.is_spam = match verdict {
+                Verdict::Spam => true,
+                Verdict::Ham => false,
+                Verdict::NeedsReview => result.is_spam,
+            };
+            result.confidence = if result.is_spam {
+                result.spam_probability
+            } else {
+                result.ham_probability
+            };
+            result.label = self.decided_label(&result.labels, result.is_spam);
+        }
+        result
+    }
+
+    /// Take the decisions of a result again for a new spam probability,
+    /// keeping its interesting tokens and explanation
+    fn redecide(
+        &self,
+        result: ClassificationResult,
+        spam_probability: f64,
+    ) -> ClassificationResult {
+        ClassificationResult {
+            interesting_tokens: result.interesting_tokens,
+            explanation: result.explanation,
+            ..self.decide(spam_probability, result.labels)
+        }
+    }
+
+    fn build_result(&self, tokens: &[String]) -> ClassificationResult {
+        let labels = self.label_scores(tokens);
+        let mut result = self.decide(self.classify_tokens(tokens), labels);
+        if self.mode == ScoringMode::Graham {
+            result.interesting_tokens = Some(self.interesting_tokens(tokens));
+        }
+        result
+    }
+
+    /// Take the spam/ham decisions for a spam probability, along with the label
+    /// probabilities reconciled with it
+    fn decide(&self, spam_probability: f64, labels: Vec<LabelProbability>) -> ClassificationResult {
+        let is_spam = match &self.costs {
+            Some(costs) => costs.is_spam(spam_probability),
+            None => spam_probability >= self.spam_threshold,
+        };
+        let labels = self.reconcile_labels(labels, spam_probability);
+
+        ClassificationResult {
+            label: self.decided_label(&labels, is_spam),
+            labels,
+            spam_probability,
+            ham_probability: 1.0 - spam_probability,
+            is_spam,
+            confidence: if is_spam {
+                spam_probability
+            } else {
+                1.0 - spam_probability
+            },
+            verdict: self.decision.verdict(spam_probability),
+            expected_costs: self.costs.map(|costs| costs.expected(spam_probability)),
+            interesting_tokens: None,
+            explanation: None,
+        }
+    }
+}
+
+/// Detailed classification result
+#[derive(Debug, Clone)]
+pub struct ClassificationResult {
+    pub spam_probability: f64,
+    pub ham_probability: f64,
+    pub is_spam: bool,
+    pub confidence: f64,
+    /// Ham label, or the most likely spam label when `is_spam`
+    pub label: String,
+    /// Probability of every label of the model, from the most to the least likely
+    ///
+    /// Spam labels add up to `spam_probability`, and the ham label gets `ham_probability`.
+    pub labels: Vec<LabelProbability>,
+    /// Ham/needs review/spam decision, independent from `is_spam`
+    pub verdict: Verdict,
+    /// Expected cost of each action, when misclassification costs are set
+    pub expected_costs: Option<ExpectedCosts>,
+    /// Tokens combined by the Graham scoring mode
+    pub interesting_tokens: Option<Vec<InterestingToken>>,
+    pub explanation: Option<Explanation>,
+}
+
+/// Contribution of a single token occurrence to the classification
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TokenContribution {
+    pub token: String,
+    /// Character offset of the token start in the original input
+    pub start: usize,
+    /// Character offset of the token end in the original input
+    pub end: usize,
+    pub counter: Counter,
+    /// Contribution to the log-odds in the scoring mode, positive values push towards spam
+    ///
+    /// In the multinomial mode, this is ln P(token|spam) - ln P(token|ham).
+    /// Other modes share the contribution of a token among its occurrences.
+    pub log_likelihood_ratio: f64,
+}
+
+/// Per-token breakdown of a classification
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Explanation {
+    pub tokens: Vec<TokenContribution>,
+    pub top_spam: Vec<TokenContribution>,
+    pub top_ham: Vec<TokenContribution>,
+}
+
+impl Explanation {
+    fn new(tokens: Vec<TokenContribution>, top_n: usize) -> Self {
+        let mut ranked: Vec<_> = tokens.iter().collect();
+        ranked.sort_by(|left, right| {
+            right
+                .log_likelihood_ratio
+                .total_cmp(&left.log_likelihood_ratio)
+        });
+
+        let mut seen = std::collections::HashSet::new();
+        ranked.retain(|contribution| seen.insert(contribution.token.as_str()));
+
+        let top_spam = ranked
+            .iter()
+            .take_while(|contribution| contribution.log_likelihood_ratio > 0.0)
+            .take(top_n)
+            .map(|&contribution| contribution.clone())
+            .collect();
+        let top_ham = ranked
+            .iter()
+            .rev()
+            .take_while(|contribution| contribution.log_likelihood_ratio < 0.0)
+            .take(top_n)
+            .map(|&contribution| contribution.clone())
+            .collect();
+
+        Self {
+            tokens,
+            top_spam,
+            top_ham,
+        }
+    }
+}
+
+/// Compute `ln(exp(a) + exp(b))` without underflowing for large negative inputs
+fn log_sum_exp(a: f64, b: f64) -> f64 {
+    let max = a.max(b);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+/// Model fixtures shared by the tests of the classifier modules
+#[cfg(test)]
+mod fixtures {
+    use super::model::{self, ModelHeader};
+    use super::{Counter, NaiveBayesClassifier, ScoringMode};
+
+    /// Build a model container from FST values, sorted by key, and a counts table
+    pub fn write_model(header: &ModelHeader, values: &[(&str, u64)], counts: &[u32]) -> Vec<u8> {
+        let mut builder = fst::MapBuilder::memory();
+        for &(key, value) in values {
+            builder.insert(key, value).unwrap();
+        }
+        let fst = builder.into_inner().unwrap();
+
+        let mut data = Vec::new();
+        let sections = model::ModelSections {
+            counts,
+            ..model::ModelSections::new(header, &fst)
+        };
+        model::write(&mut data, sections).unwrap();
+        data
+    }
+
+    /// Build a spam/ham model from token counters sorted by token
+    ///
+    /// Token totals and the vocabulary size of the header are derived from the counters.
+    pub fn build_model(counters: &[(&str, Counter)], header: ModelHeader) -> Vec<u8> {
+        let header = ModelHeader {
+            spam_tokens: counters.iter().map(|(_, counter)| counter.spam).sum(),
+            ham_tokens: counters.iter().map(|(_, counter)| counter.ham).sum(),
+            vocabulary_size: counters.len() as u32,
+            ..header
+        };
+        let values: Vec<_> = counters
+            .iter()
+            .map(|&(key, counter)| (key, counter.to_u64()))
+            .collect();
+        write_model(&header, &values, &[])
+    }
+
+    pub fn classifier(data: &[u8], mode: ScoringMode) -> NaiveBayesClassifier<&[u8]> {
+        let mut classifier = NaiveBayesClassifier::from_bytes(data).unwrap();
+        classifier.set_mode(mode);
+        classifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(input: &str) -> f64 {
+        let classifier = NaiveBayesClassifier::new();
+        classifier.classify(input)
+    }
+
+    /// Generate spam-like text with specified number of tokens using random selection
+    fn generate_spam_text(token_count: usize) -> String {
+        use rand::seq::IndexedRandom;
+
+        let spam_words = vec![
+            "FREE",
+            "MONEY",
+            "WIN",
+            "CLICK",
+            "NOW",
+            "URGENT",
+            "LIMITED",
+            "OFFER",
+            "GUARANTEED",
+            "CASH",
+            "PRIZE",
+            "WINNER",
+            "CONGRATULATIONS",
+            "CLAIM",
+            "EXCLUSIVE",
+            "DEAL",
+            "DISCOUNT",
+            "SAVE",
+            "BUY",
+            "CHEAP",
+        ];
+
+        let mut rng = rand::rng();
+        (0..token_count)
+            .filter_map(|_| spam_words.choose(&mut rng).copied())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn test_tokenization() {
+        let text = "Hello world! This is a test message.";
+        let tokens = tokenizer::tokenize(text);
+
+        println!("Tokens: {:?}", tokens);
+
+        assert!(!tokens.is_empty());
+        assert!(tokens.contains(&"hello".to_string()));
+        assert!(tokens.contains(&"world".to_string()));
+        assert!(tokens.contains(&"test".to_string()));
+        // Check for either "message" or "messag" (stemmed version)
+        assert!(
+            tokens.contains(&"message".to_string()) || tokens.contains(&"messag".to_string()),
+            "Expected 'message' or 'messag' in tokens: {:?}",
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let score = classify("");
+        assert!(score >= 0.0 && score <= 1.0);
+    }
+
+    #[test]
+    fn test_spam_indicators() {
+        let spam_text = "FREE MONEY! Click here to win $1000000! Limited time offer!";
+        let score = classify(spam_text);
+
+        // Should have higher spam probability
+        assert!(score > 0.5);
+    }
+
+    #[test]
+    fn test_ham_indicators() {
+        let ham_text = "Hello, how are you doing today? I hope you have a great day.";
+        let score = classify(ham_text);
+
+        // Should have lower spam probability
+        assert!(score < 0.8);
+    }
+
+    #[test]
+    fn test_classifier_stats() {
+        let stats = ClassifierStats::new();
+        assert_eq!(stats.total_spam, 0);
+        assert_eq!(stats.total_ham, 0);
+        assert_eq!(stats.total_tokens, 0);
+        assert_eq!(stats.unique_tokens, 0);
+        assert_eq!(stats.prior_spam(), 0.5);
+        assert_eq!(stats.prior_ham(), 0.5);
+    }
+
+    #[test]
+    fn test_document_priors() {
+        // Few long spam messages must not outweigh many short ham messages
+        let stats = ClassifierStats {
+            total_spam: 900,
+            total_ham: 100,
+            total_tokens: 1000,
+            unique_tokens: 50,
+            spam_documents: 10,
+            ham_documents: 30,
+        };
+        assert_eq!(stats.prior_spam(), 0.25);
+        assert_eq!(stats.prior_ham(), 0.75);
+
+        // Legacy models without document counts fall back to token counts
+        let legacy = ClassifierStats {
+            spam_documents: 0,
+            ham_documents: 0,
+            ..stats
+        };
+        assert_eq!(legacy.prior_spam(), 0.9);
+    }
+
+    #[test]
+    fn test_load_legacy_and_container_models() {
+        let mut builder = fst::MapBuilder::memory();
+        builder
+            .insert("free", Counter { spam: 8, ham: 2 }.to_u64())
+            .unwrap();
+        builder
+            .insert("hello", Counter { spam: 1, ham: 9 }.to_u64())
+            .unwrap();
+        let fst = builder.into_inner().unwrap();
+
+        let legacy = NaiveBayesClassifier::from_bytes(&fst).unwrap();
+        assert_eq!(legacy.stats().total_spam, 9);
+        assert_eq!(legacy.stats().total_ham, 11);
+        assert_eq!(legacy.stats().unique_tokens, 2);
+        assert_eq!(legacy.tokenizer(), &TokenizerConfig::default());
+
+        let header = ModelHeader {
+            spam_documents: 1,
+            ham_documents: 3,
+            spam_tokens: 9,
+            ham_tokens: 11,
+            vocabulary_size: 2,
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        model::write(&mut data, model::ModelSections::new(&header, &fst)).unwrap();
+
+        let classifier = NaiveBayesClassifier::from_bytes(&data).unwrap();
+        assert_eq!(classifier.stats().total_spam, 9);
+        assert_eq!(classifier.stats().prior_spam(), 0.25);
+        assert!(classifier.classify("free free free") > classifier.classify("hello hello"));
+    }
+
+    #[test]
+    fn test_tokenizer_fingerprint() {
+        let mut builder = fst::MapBuilder::memory();
+        builder
+            .insert("free", Counter { spam: 8, ham: 2 }.to_u64())
+            .unwrap();
+        let fst = builder.into_inner().unwrap();
+
+        let load = |tokenizer_fingerprint: Option<String>| {
+            let header = ModelHeader {
+                spam_documents: 1,
+                ham_documents: 1,
+                spam_tokens: 8,
+                ham_tokens: 2,
+                vocabulary_size: 1,
+                tokenizer_fingerprint,
+                ..Default::default()
+            };
+            let mut data = Vec::new();
+            model::write(&mut data, model::ModelSections::new(&header, &fst)).unwrap();
+            NaiveBayesClassifier::from_bytes(&data).map(|_| ())
+        };
+
+        // Models without a fingerprint are checked against the baseline tokenizer
+        assert!(load(None).is_ok());
+        assert!(load(Some(TokenizerConfig::default().fingerprint())).is_ok());
+
+        // Models trained with another tokenization pipeline are rejected
+        let err = load(Some("0123456789abcdef".to_string())).unwrap_err();
+        assert!(err.to_string().contains("retrain the model"), "{err}");
+    }
+
+    #[test]
+    fn test_counter_serialization() {
+        let counter = Counter { spam: 10, ham: 5 };
+        let serialized = counter.to_u64();
+        let deserialized = Counter::from_u64(serialized);
+
+        assert_eq!(counter.spam, deserialized.spam);
+        assert_eq!(counter.ham, deserialized.ham);
+    }
+
+    #[test]
+    fn test_classification_result() {
+        let result = ClassificationResult {
+            spam_probability: 0.8,
+            ham_probability: 0.2,
+            is_spam: true,
+            confidence: 0.8,
+            label: LABEL_SPAM.to_string(),
+            labels: Vec::new(),
+            verdict: Verdict::Spam,
+            expected_costs: None,
+            interesting_tokens: None,
+            explanation: None,
+        };
+
+        assert_eq!(result.spam_probability, 0.8);
+        assert_eq!(result.ham_probability, 0.2);
+        assert!(result.is_spam);
+        assert_eq!(result.confidence, 0.8);
+    }
+
+    #[test]
+    fn test_apply_domain_lists() {
+        use domains::DomainList;
+
+        let classifier = NaiveBayesClassifier::new();
+        let allowed = [ListMatch {
+            list: DomainList::Allowlist,
+            domain: "example.com".to_string(),
+            url: "https://example.com".to_string(),
+        }];
+
+        let result = classifier.classify_explained("FREE MONEY! Click here now!", 5);
+        let result = classifier.apply_domain_lists(result, &allowed, ListAction::Override);
+        assert_eq!(result.spam_probability, 0.0);
+        assert!(!result.is_spam);
+        assert_eq!(result.verdict, Verdict::Ham);
+        assert!(result.explanation.is_some());
+
+        let result = classifier.classify_detailed("Hello there");
+        let unchanged = classifier.apply_domain_lists(result.clone(), &[], ListAction::Override);
+        assert_eq!(unchanged.spam_probability, result.spam_probability);
+    }
+
+    #[test]
+    fn test_apply_rules() {
+        let classifier = NaiveBayesClassifier::new();
+        let rules = rules::RuleSet::parse(
+            r#"[
+                {"name": "boost", "literal": "hello", "score": 50.0},
+                {"name": "review", "literal": "there", "verdict": "needs_review"},
+                {"name": "allow", "literal": "there", "verdict": "ham"}
+            ]"#,
+        )
+        .unwrap();
+
+        let text = "Hello there";
+        let hits = rules.evaluate(text);
+        let result = classifier.apply_rules(classifier.classify_detailed(text), &hits);
+
+        // The score shift makes it spam, the first verdict only asks for a review
+        assert!(result.spam_probability > 0.99);
+        assert!(result.is_spam);
+        assert_eq!(result.verdict, Verdict::NeedsReview);
+
+        let unchanged = classifier.apply_rules(classifier.classify_detailed(text), &[]);
+        assert_eq!(unchanged.spam_probability, classifier.classify(text));
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        // Test with very long text
+        let long_text = "This is a very long text ".repeat(100);
+        let score = classify(&long_text);
+        println!("Long text score: {}", score);
+        assert!(score >= 0.0 && score <= 1.0, "Long text score: {}", score);
+
+        // Test with special characters
+        let special_text = "!@#$%^&*()_+-=[]{}|;':\",./<>?";
+        let score = classify(special_text);
+        println!("Special chars score: {}", score);
+        assert!(
+            score >= 0.0 && score <= 1.0,
+            "Special chars score: {}",
+            score
+        );
+
+        // Test with numbers only
+        let numbers = "1234567890";
+        let score = classify(numbers);
+        println!("Numbers score: {}", score);
+        assert!(score >= 0.0 && score <= 1.0, "Numbers score: {}", score);
+    }
+
+    #[test]
+    fn test_probability_bounds() {
+        let texts = vec![
+            "Normal email content",
+            "FREE MONEY NOW!!!",
+            "Meeting tomorrow at 3pm",
+            "BUY VIAGRA CHEAP!!!",
+            "Hello, how are you?",
+        ];
+
+        for text in texts {
+            let score = classify(text);
+            assert!(
+                score >= 0.0 && score <= 1.0,
+                "Score {} for text '{}' is out of bounds",
+                score,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_classifier_instance() {
+        let classifier = NaiveBayesClassifier::new();
+        let result = classifier.classify_detailed("Test message");
+
+        assert!(result.spam_probability >= 0.0 && result.spam_probability <= 1.0);
+        assert!(result.ham_probability >= 0.0 && result.ham_probability <= 1.0);
+        assert!((result.spam_probability + result.ham_probability - 1.0).abs() < 0.001);
+        assert!(result.confidence >= 0.0 && result.confidence <= 1.0);
+    }
+
+    #[test]
+    fn test_classify_explained() {
+        let classifier = NaiveBayesClassifier::new();
+        let text = "FREE MONEY! Click here to win, free money. Hello, how are you?";
+
+        let detailed = classifier.classify_detailed(text);
+        assert!(detailed.explanation.is_none());
+
+        let result = classifier.classify_explained(text, 2);
+        assert_eq!(result.spam_probability, detailed.spam_probability);
+
+        let explanation = result.explanation.unwrap();
+        assert_eq!(
+            explanation.tokens.len(),
+            classifier.tokenizer().tokenize(text).len()
+        );
+        assert!(explanation.top_spam.len() <= 2);
+        assert!(explanation.top_ham.len() <= 2);
+        assert!(explanation
+            .top_spam
+            .iter()
+            .all(|contribution| contribution.log_likelihood_ratio > 0.0));
+        assert!(explanation
+            .top_ham
+            .iter()
+            .all(|contribution| contribution.log_likelihood_ratio < 0.0));
+
+        // Repeated tokens are only listed once among the top contributors
+        let top_spam: Vec<_> = explanation.top_spam.iter().map(|c| &c.token).collect();
+        assert!(top_spam.len() < 2 || top_spam[0] != top_spam[1]);
+
+        // Token contributions add up to the log-odds on top of the prior
+        let prior = classifier.stats.prior_spam();
+        let sum: f64 = explanation
+            .tokens
+            .iter()
+            .map(|contribution| contribution.log_likelihood_ratio)
+            .sum();
+        let log_odds = classifier.classify_log_odds(text);
+        assert!((log_odds - (prior / (1.0 - prior)).ln() - sum).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_model_ngram_order() {
+        let mut builder = fst::MapBuilder::memory();
+        builder
+            .insert("act", Counter { spam: 1, ham: 1 }.to_u64())
+            .unwrap();
+        builder
+            .insert("ngram:act now", Counter { spam: 9, ham: 0 }.to_u64())
+            .unwrap();
+        builder
+            .insert("now", Counter { spam: 1, ham: 1 }.to_u64())
+            .unwrap();
+        let fst = builder.into_inner().unwrap();
+
+        let build = |ngram_order| {
+            let header = ModelHeader {
+                spam_documents: 1,
+                ham_documents: 1,
+                spam_tokens: 11,
+                ham_tokens: 2,
+                vocabulary_size: 3,
+                tokenizer: TokenizerConfig {
+                    ngram_order,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut data = Vec::new();
+            model::write(&mut data, model::ModelSections::new(&header, &fst)).unwrap();
+            data
+        };
+
+        let unigram_data = build(1);
+        let unigram = NaiveBayesClassifier::from_bytes(&unigram_data).unwrap();
+        let bigram_data = build(2);
+        let bigram = NaiveBayesClassifier::from_bytes(&bigram_data).unwrap();
+
+        // The classifier picks up the n-gram order recorded in the model
+        assert_eq!(bigram.tokenizer().ngram_order, 2);
+        assert!(bigram.classify("act now") > unigram.classify("act now"));
+
+        let explanation = bigram.classify_explained("act now", 1).explanation.unwrap();
+        assert_eq!(explanation.top_spam[0].token, "ngram:act now");
+    }
+
+    #[test]
+    fn test_alpha_methods() {
+        let mut classifier = NaiveBayesClassifier::new();
+
+        // Test default alpha value
+        assert_eq!(classifier.alpha(), DEFAULT_ALPHA);
+
+        // Test setting alpha value
+        classifier.set_alpha(2.0);
+        assert_eq!(classifier.alpha(), 2.0);
+
+        // Test classification still works with different alpha
+        let result = classifier.classify("Test message");
+        assert!(result >= 0.0 && result <= 1.0);
+    }
+
+    #[test]
+    fn test_shared_classifier_overlay() {
+        let shared = NaiveBayesClassifier::shared();
+        assert!(std::ptr::eq(shared, NaiveBayesClassifier::shared()));
+
+        let mut overlay = shared.clone();
+        overlay.set_alpha(2.0);
+        overlay.set_spam_threshold(0.5);
+
+        let mut fresh = NaiveBayesClassifier::new();
+        fresh.set_alpha(2.0);
+
+        let text = "FREE MONEY! Click here to win $1000000!";
+        assert_eq!(overlay.classify(text), fresh.classify(text));
+        assert_eq!(overlay.spam_threshold(), 0.5);
+
+        // The shared classifier keeps its defaults
+        assert_eq!(shared.alpha(), DEFAULT_ALPHA);
+        assert_eq!(shared.spam_threshold(), SPAM_TRESHOLD);
+    }
+
+    #[test]
+    fn test_moderate_length_text_no_fallback() {
+        let classifier = NaiveBayesClassifier::new();
+
+        // Create text with ~30 tokens (moderate length)
+        let moderate_spam_text = generate_spam_text(30);
+
+        let result = classifier.classify(&moderate_spam_text);
+        let prior_spam = classifier.stats.prior_spam();
+
+        println!("Moderate spam text result: {}", result);
+        println!("Prior spam probability: {}", prior_spam);
+        println!(
+            "Text length: {} tokens",
+            moderate_spam_text.split_whitespace().count()
+        );
+
+        // Verify the result is not NaN or infinite
+        assert!(result.is_finite(), "Result should be finite: {}", result);
+        assert!(
+            result >= 0.0 && result <= 1.0,
+            "Result should be in [0,1]: {}",
+            result
+        );
+
+        // For moderate length text, it should NOT hit the fallback
+        // Allow for some floating point precision issues but check it's meaningfully different
+        assert!(
+            (result - prior_spam).abs() > 0.001,
+            "Moderate text should not fall back to prior. Result: {}, Prior: {}",
+            result,
+            prior_spam
+        );
+
+        // For spam text, it should have high probability
+        assert!(
+            result > 0.5,
+            "Moderate spam text should have high spam probability: {}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_extremely_long_text_no_fallback() {
+        let classifier = NaiveBayesClassifier::new();
+
+        // A 5,000-word spam email used to underflow and fall back to the prior
+        let extremely_long_text = generate_spam_text(5000);
+
+        let result = classifier.classify(&extremely_long_text);
+        let prior_spam = classifier.stats.prior_spam();
+
+        println!("Extremely long text result: {}", result);
+        println!("Prior spam probability: {}", prior_spam);
+        println!(
+            "Text length: {} tokens",
+            extremely_long_text.split_whitespace().count()
+        );
+
+        assert!(
+            result.is_finite(),
+            "Result should be finite even for long text: {}",
+            result
+        );
+        assert!(
+            (0.0..=1.0).contains(&result),
+            "Result should be in [0,1] even for long text: {}",
+            result
+        );
+        assert!(
+            (result - prior_spam).abs() > 0.001,
+            "Long text should not fall back to prior. Result: {}, Prior: {}",
+            result,
+            prior_spam
+        );
+        assert!(
+            result > 0.5,
+            "Long spam text should have high spam probability: {}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_log_odds() {
+        let classifier = NaiveBayesClassifier::new();
+
+        let spam_odds = classifier.classify_log_odds("FREE MONEY! Click here to win!");
+        let ham_odds = classifier.classify_log_odds("Hello, how are you doing today?");
+        assert!(spam_odds > ham_odds);
+
+        // The log-odds keep growing where the probability saturates
+        let long_odds = classifier.classify_log_odds(&"free money win cash ".repeat(1000));
+        assert!(long_odds.is_finite());
+        assert!(long_odds > spam_odds);
+
+        // Empty input only carries the prior
+        let prior = classifier.stats.prior_spam();
+        let empty_odds = classifier.classify_log_odds("");
+        assert!((empty_odds - (prior / (1.0 - prior)).ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_log_sum_exp() {
+        assert!((log_sum_exp(0.0, 0.0) - 2f64.ln()).abs() < 1e-12);
+        assert!((log_sum_exp(-10000.0, -10000.0) - (-10000.0 + 2f64.ln())).abs() < 1e-9);
+        assert_eq!(log_sum_exp(-5000.0, -1.0e9), -5000.0);
+        assert_eq!(
+            log_sum_exp(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            f64::NEG_INFINITY
+        );
+    }
+}
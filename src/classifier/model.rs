@@ -0,0 +1,477 @@
+//! Versioned model container
+//!
+//! A model file starts with a magic marker, followed by the format version, the
+//! length of a JSON metadata header, the header itself and finally the raw FST
+//! bytes. Files without the magic marker are treated as legacy bare FST models.
+//!
+//! Multi-class models (format version 2 and later, with a non-empty label list)
+//! store a table of little-endian `u32` counts between the header and the FST,
+//! one row of `labels.len()` counts per token. Their FST values are row indices
+//! instead of packed spam/ham counters.
+//!
+//! Language-specific sub-models (format version 3 and later), each a complete
+//! container of its own, are stored right after the header, before the counts
+//! table.
+//!
+//! Domain lists (format version 4 and later), an FST of known-bad and known-good
+//! domains, are stored between the header and the language sub-models.
+
+use std::io::Write;
+use std::sync::LazyLock;
+
+use anyhow::Result;
+
+use super::calibration::Calibration;
+use super::TokenizerConfig;
+
+/// Fingerprint of the [baseline tokenizer](TokenizerConfig::default)
+///
+/// Models trained before fingerprints, bare FST models included, don't record
+/// the fingerprint of their tokenizer. They are checked against this one
+/// instead, catching changes to the baseline stages such as segmentation or
+/// stemming, though not to the stages their settings may have added.
+pub const BASELINE_TOKENIZER_FINGERPRINT: &str = "a6b7d803c2d0596c";
+
+/// Marker identifying a model container
+pub const MAGIC: &[u8; 8] = b"EDGSPAM\0";
+
+/// Current model container format version
+pub const FORMAT_VERSION: u32 = 4;
+
+const PREAMBLE_LEN: usize = MAGIC.len() + 4 + 4;
+
+/// Metadata stored alongside the FST
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ModelHeader {
+    /// Number of spam documents the model was trained on
+    pub spam_documents: u32,
+    /// Number of ham documents the model was trained on
+    pub ham_documents: u32,
+    /// Sum of all spam token counts
+    pub spam_tokens: u32,
+    /// Sum of all ham token counts
+    pub ham_tokens: u32,
+    /// Number of distinct tokens in the FST
+    pub vocabulary_size: u32,
+    /// Tokenizer configuration used at training time
+    pub tokenizer: TokenizerConfig,
+    /// Fingerprint of the tokenizer used at training time, `None` for models
+    /// trained before fingerprints
+    pub tokenizer_fingerprint: Option<String>,
+    /// Creation time, in seconds since the Unix epoch
+    pub created_at: u64,
+    /// Labels of a multi-class model, in counts table column order
+    pub labels: Vec<LabelStats>,
+    /// Label standing for legitimate messages, every other label counts as spam
+    pub ham_label: String,
+    /// Language-specific sub-models, in storage order
+    pub languages: Vec<LanguageEntry>,
+    /// Map from log-odds to calibrated probabilities, fitted on held-out documents
+    pub calibration: Option<Calibration>,
+    /// Smoothing factor of the scores the calibration map was fitted on, `None`
+    /// for models trained before it was recorded, fitted with the default one
+    pub calibration_alpha: Option<f64>,
+    /// Size of the domain lists FST in bytes, 0 when the model has none
+    pub domain_lists_length: u64,
+}
+
+impl ModelHeader {
+    /// Check that the runtime tokenizer tokenizes inputs the way the model was trained with
+    pub fn check_tokenizer(&self) -> Result<()> {
+        match &self.tokenizer_fingerprint {
+            Some(expected) => compare_fingerprints(expected, &self.tokenizer.fingerprint()),
+            None => check_baseline_tokenizer(),
+        }
+    }
+}
+
+/// Check that the baseline tokenizer tokenizes inputs the way models without a fingerprint were trained with
+pub fn check_baseline_tokenizer() -> Result<()> {
+    static BASELINE: LazyLock<String> = LazyLock::new(|| TokenizerConfig::default().fingerprint());
+    compare_fingerprints(BASELINE_TOKENIZER_FINGERPRINT, &BASELINE)
+}
+
+fn compare_fingerprints(expected: &str, actual: &str) -> Result<()> {
+    if expected != actual {
+        anyhow::bail!(
+            "Model was trained with tokenizer fingerprint {expected}, but the runtime tokenizer has fingerprint {actual}: the tokenization pipeline changed, retrain the model"
+        );
+    }
+    Ok(())
+}
+
+/// Location of a language-specific sub-model
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LanguageEntry {
+    /// ISO 639-3 code of the language, as detected by the tokenizer
+    pub language: String,
+    /// Size of the sub-model container in bytes
+    pub length: u64,
+}
+
+/// Training statistics of a single label
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LabelStats {
+    pub name: String,
+    /// Number of documents with this label
+    pub documents: u32,
+    /// Sum of the token counts for this label
+    pub tokens: u32,
+}
+
+/// A parsed model file, borrowing the FST bytes from the input
+#[derive(Debug, Clone)]
+pub struct ModelFile<'a> {
+    /// Metadata header, `None` for legacy bare FST models
+    pub header: Option<ModelHeader>,
+    /// Domain lists FST, empty when the model has none
+    pub domain_lists: &'a [u8],
+    /// Language codes and sub-model containers
+    pub languages: Vec<(String, &'a [u8])>,
+    /// Per-label counts table, empty for binary models
+    pub counts: &'a [u8],
+    pub fst: &'a [u8],
+}
+
+impl<'a> ModelFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(MAGIC) {
+            return Ok(Self {
+                header: None,
+                domain_lists: &[],
+                languages: Vec::new(),
+                counts: &[],
+                fst: data,
+            });
+        }
+
+        if data.len() < PREAMBLE_LEN {
+            anyhow::bail!("Truncated model header");
+        }
+
+        let version = read_u32(&data[MAGIC.len()..]);
+        if version > FORMAT_VERSION {
+            anyhow::bail!("Unsupported model format version: {version}");
+        }
+
+        let header_len = read_u32(&data[MAGIC.len() + 4..]) as usize;
+        let header_end = PREAMBLE_LEN + header_len;
+        if data.len() < header_end {
+            anyhow::bail!("Truncated model header");
+        }
+
+        let header: ModelHeader = serde_json::from_slice(&data[PREAMBLE_LEN..header_end])?;
+
+        let domain_lists_end = header_end + header.domain_lists_length as usize;
+        if data.len() < domain_lists_end {
+            anyhow::bail!("Truncated domain lists");
+        }
+
+        let mut languages = Vec::with_capacity(header.languages.len());
+        let mut languages_end = domain_lists_end;
+        for entry in &header.languages {
+            let end = languages_end + entry.length as usize;
+            if data.len() < end {
+                anyhow::bail!("Truncated {} sub-model", entry.language);
+            }
+
+            languages.push((entry.language.clone(), &data[languages_end..end]));
+            languages_end = end;
+        }
+
+        let counts_len = header.labels.len() * header.vocabulary_size as usize * 4;
+        let counts_end = languages_end + counts_len;
+        if data.len() < counts_end {
+            anyhow::bail!("Truncated model counts table");
+        }
+
+        Ok(Self {
+            header: Some(header),
+            domain_lists: &data[header_end..domain_lists_end],
+            languages,
+            counts: &data[languages_end..counts_end],
+            fst: &data[counts_end..],
+        })
+    }
+}
+
+/// Sections of a model container, in the order [`write`] stores them
+#[derive(Debug, Clone, Copy)]
+pub struct ModelSections<'a> {
+    /// Metadata header, whose section lengths and language entries are filled on write
+    pub header: &'a ModelHeader,
+    /// Domain lists FST, empty for models without any
+    pub domain_lists: &'a [u8],
+    /// Language codes and sub-model containers
+    pub languages: &'a [(String, Vec<u8>)],
+    /// Per-label counts table, empty for binary models
+    pub counts: &'a [u32],
+    pub fst: &'a [u8],
+}
+
+impl<'a> ModelSections<'a> {
+    /// Sections of a binary model without domain lists or language sub-models
+    #[allow(dead_code)]
+    pub fn new(header: &'a ModelHeader, fst: &'a [u8]) -> Self {
+        Self {
+            header,
+            domain_lists: &[],
+            languages: &[],
+            counts: &[],
+            fst,
+        }
+    }
+}
+
+/// Write a model container
+///
+/// The domain lists length and language entries of the header are filled from
+/// the sections.
+#[allow(dead_code)]
+pub fn write<W: Write>(mut writer: W, sections: ModelSections) -> Result<()> {
+    let ModelSections {
+        header,
+        domain_lists,
+        languages,
+        counts,
+        fst,
+    } = sections;
+
+    let expected_counts = header.labels.len() * header.vocabulary_size as usize;
+    if counts.len() != expected_counts {
+        anyhow::bail!(
+            "Counts table has {} entries, expected {expected_counts}",
+            counts.len()
+        );
+    }
+
+    let header = ModelHeader {
+        languages: languages
+            .iter()
+            .map(|(language, data)| LanguageEntry {
+                language: language.clone(),
+                length: data.len() as u64,
+            })
+            .collect(),
+        domain_lists_length: domain_lists.len() as u64,
+        ..header.clone()
+    };
+    let header = serde_json::to_vec(&header)?;
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(&header)?;
+    writer.write_all(domain_lists)?;
+    for (_, data) in languages {
+        writer.write_all(data)?;
+    }
+    for count in counts {
+        writer.write_all(&count.to_le_bytes())?;
+    }
+    writer.write_all(fst)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_fst() -> Vec<u8> {
+        let mut builder = fst::MapBuilder::memory();
+        builder.insert("free", 42).unwrap();
+        builder.insert("hello", 7).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn build_container(header: &ModelHeader) -> Vec<u8> {
+        let mut data = Vec::new();
+        write(&mut data, ModelSections::new(header, &build_fst())).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_container_roundtrip() {
+        let header = ModelHeader {
+            spam_documents: 3,
+            ham_documents: 5,
+            spam_tokens: 12,
+            ham_tokens: 20,
+            vocabulary_size: 2,
+            tokenizer: TokenizerConfig::default(),
+            created_at: 1_700_000_000,
+            ..Default::default()
+        };
+
+        let data = build_container(&header);
+
+        let file = ModelFile::parse(&data).unwrap();
+        assert_eq!(file.header, Some(header));
+        assert_eq!(file.fst, build_fst().as_slice());
+
+        let map = fst::Map::new(file.fst).unwrap();
+        assert_eq!(map.get("free"), Some(42));
+    }
+
+    #[test]
+    fn test_multiclass_counts_table() {
+        let header = ModelHeader {
+            vocabulary_size: 2,
+            labels: vec![
+                LabelStats {
+                    name: "phishing".to_string(),
+                    documents: 1,
+                    tokens: 3,
+                },
+                LabelStats {
+                    name: "promotional".to_string(),
+                    documents: 2,
+                    tokens: 4,
+                },
+                LabelStats {
+                    name: "legitimate".to_string(),
+                    documents: 3,
+                    tokens: 5,
+                },
+            ],
+            ham_label: "legitimate".to_string(),
+            ..Default::default()
+        };
+        let counts = [1, 2, 3, 2, 2, 2];
+        let fst = build_fst();
+        let sections = |counts| ModelSections {
+            counts,
+            ..ModelSections::new(&header, &fst)
+        };
+
+        let mut data = Vec::new();
+        write(&mut data, sections(&counts)).unwrap();
+
+        let file = ModelFile::parse(&data).unwrap();
+        assert_eq!(file.header, Some(header.clone()));
+        assert_eq!(file.counts.len(), counts.len() * 4);
+        assert_eq!(&file.counts[12..16], &2u32.to_le_bytes());
+        assert_eq!(file.fst, build_fst().as_slice());
+
+        // The table size must match the header
+        let mut data = Vec::new();
+        assert!(write(&mut data, sections(&counts[..4])).is_err());
+    }
+
+    #[test]
+    fn test_language_submodels() {
+        let english = build_container(&ModelHeader {
+            spam_documents: 1,
+            ..Default::default()
+        });
+        let french = build_container(&ModelHeader {
+            spam_documents: 2,
+            ..Default::default()
+        });
+        let languages = vec![("eng".to_string(), english), ("fra".to_string(), french)];
+
+        let mut data = Vec::new();
+        write(
+            &mut data,
+            ModelSections {
+                languages: &languages,
+                ..ModelSections::new(&ModelHeader::default(), &build_fst())
+            },
+        )
+        .unwrap();
+
+        let file = ModelFile::parse(&data).unwrap();
+        assert_eq!(file.header.unwrap().languages.len(), 2);
+        assert_eq!(file.fst, build_fst().as_slice());
+        assert_eq!(file.languages.len(), 2);
+
+        let (language, submodel) = &file.languages[1];
+        assert_eq!(language, "fra");
+        let submodel = ModelFile::parse(submodel).unwrap();
+        assert_eq!(submodel.header.unwrap().spam_documents, 2);
+        assert_eq!(submodel.fst, build_fst().as_slice());
+    }
+
+    #[test]
+    fn test_domain_lists() {
+        let mut lists = fst::MapBuilder::memory();
+        lists.insert("example.com", 1).unwrap();
+        let lists = lists.into_inner().unwrap();
+
+        let english = build_container(&ModelHeader::default());
+        let languages = vec![("eng".to_string(), english)];
+
+        let mut data = Vec::new();
+        write(
+            &mut data,
+            ModelSections {
+                domain_lists: &lists,
+                languages: &languages,
+                ..ModelSections::new(&ModelHeader::default(), &build_fst())
+            },
+        )
+        .unwrap();
+
+        let file = ModelFile::parse(&data).unwrap();
+        assert_eq!(file.domain_lists, lists.as_slice());
+        assert_eq!(file.languages[0].1, languages[0].1.as_slice());
+        assert_eq!(file.fst, build_fst().as_slice());
+
+        // Containers without domain lists have an empty section
+        let file = ModelFile::parse(&languages[0].1).unwrap();
+        assert!(file.domain_lists.is_empty());
+    }
+
+    #[test]
+    fn test_legacy_bare_fst() {
+        let fst = build_fst();
+
+        let file = ModelFile::parse(&fst).unwrap();
+        assert!(file.header.is_none());
+        assert!(file.languages.is_empty());
+        assert!(file.counts.is_empty());
+        assert_eq!(file.fst, fst.as_slice());
+    }
+
+    #[test]
+    fn test_header_without_tokenizer() {
+        // Headers written before tokenizer settings existed get the baseline ones
+        let header: ModelHeader = serde_json::from_str(r#"{"spam_documents": 3}"#).unwrap();
+        assert_eq!(header.tokenizer, TokenizerConfig::default());
+        assert!(!header.tokenizer.urls && !header.tokenizer.placeholders);
+
+        // Settings added later are off in headers that predate them
+        let header: ModelHeader =
+            serde_json::from_str(r#"{"tokenizer": {"ngram_order": 2}}"#).unwrap();
+        assert_eq!(header.tokenizer.ngram_order, 2);
+        assert!(!header.tokenizer.style && !header.tokenizer.deobfuscate);
+    }
+
+    #[test]
+    fn test_rejects_future_version() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        assert!(ModelFile::parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated_header() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        data.extend_from_slice(&64u32.to_le_bytes());
+        data.extend_from_slice(b"{}");
+
+        assert!(ModelFile::parse(&data).is_err());
+    }
+}
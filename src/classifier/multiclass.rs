@@ -0,0 +1,311 @@
+//! Multi-class classification
+//!
+//! Multi-class models keep a row of per-label counts for every token in a table
+//! next to the FST. The binary spam/ham API works on top of them by pooling all
+//! labels but the ham one into spam.
+
+use super::model::LabelStats;
+use super::{log_sum_exp, Counter, Document, NaiveBayesClassifier, LABEL_HAM, LABEL_SPAM};
+
+/// Labels and per-token counts table of a multi-class model
+#[derive(Debug, Clone)]
+pub struct LabelTable<D> {
+    labels: Vec<LabelStats>,
+    ham_index: Option<usize>,
+    counts: D,
+}
+
+impl<D: AsRef<[u8]>> LabelTable<D> {
+    pub fn new(labels: Vec<LabelStats>, ham_label: &str, counts: D) -> Self {
+        let ham_index = labels.iter().position(|label| label.name == ham_label);
+
+        Self {
+            labels,
+            ham_index,
+            counts,
+        }
+    }
+
+    pub fn labels(&self) -> &[LabelStats] {
+        &self.labels
+    }
+
+    pub fn ham_label(&self) -> Option<&str> {
+        self.ham_index.map(|index| self.labels[index].name.as_str())
+    }
+
+    /// Get the count of a token row for the label at the given column
+    pub fn count(&self, row: u64, label: usize) -> u32 {
+        let offset = (row as usize * self.labels.len() + label) * 4;
+        let bytes = &self.counts.as_ref()[offset..offset + 4];
+
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// Pool a token row into spam and ham counts
+    pub fn counter(&self, row: u64) -> Counter {
+        let mut counter = Counter::default();
+
+        for label in 0..self.labels.len() {
+            let count = self.count(row, label);
+            if Some(label) == self.ham_index {
+                counter.ham += count;
+            } else {
+                counter.spam += count;
+            }
+        }
+
+        counter
+    }
+}
+
+/// Posterior probability of a single label
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LabelProbability {
+    pub label: String,
+    pub probability: f64,
+}
+
+impl<D: AsRef<[u8]>> NaiveBayesClassifier<D> {
+    /// Classify text against every label of the model
+    ///
+    /// Probabilities are sorted from the most to the least likely label. Binary
+    /// models report the `spam` and `ham` labels.
+    #[allow(dead_code)]
+    pub fn classify_labels(&self, document: &(impl Document + ?Sized)) -> Vec<LabelProbability> {
+        self.classify_detailed(document).labels
+    }
+
+    /// Score the tokens against every label of the model
+    ///
+    /// Multi-class models score their labels with the multinomial variant,
+    /// whatever the scoring mode, before [`reconcile_labels`](Self::reconcile_labels)
+    /// aligns them with the spam probability. Binary models have nothing to
+    /// score beyond the spam probability itself.
+    pub(super) fn label_scores(&self, tokens: &[String]) -> Vec<LabelProbability> {
+        let Some(table) = &self.labels else {
+            return [LABEL_SPAM, LABEL_HAM]
+                .into_iter()
+                .map(|label| LabelProbability {
+                    label: label.to_string(),
+                    probability: 0.5,
+                })
+                .collect();
+        };
+
+        let labels = table.labels();
+        let total_documents: u32 = labels.iter().map(|label| label.documents).sum();
+        let smoothing = self.alpha * self.stats.unique_tokens as f64;
+
+        let denominators: Vec<f64> = labels
+            .iter()
+            .map(|label| label.tokens as f64 + smoothing)
+            .collect();
+
+        // Calculate log probabilities to avoid numerical underflow
+        let mut log_probs: Vec<f64> = labels
+            .iter()
+            .map(|label| {
+                if total_documents == 0 {
+                    (1.0 / labels.len() as f64).ln()
+                } else {
+                    (label.documents as f64 / total_documents as f64).ln()
+                }
+            })
+            .collect();
+
+        for token in tokens {
+            let row = self.model.get(token);
+
+            for (label, log_prob) in log_probs.iter_mut().enumerate() {
+                let count = row.map_or(0, |row| table.count(row, label));
+                *log_prob += ((count as f64 + self.alpha) / denominators[label]).ln();
+            }
+        }
+
+        let log_denominator = log_probs.iter().fold(f64::NEG_INFINITY, |acc, &log_prob| {
+            log_sum_exp(acc, log_prob)
+        });
+
+        labels
+            .iter()
+            .zip(log_probs)
+            .map(|(label, log_prob)| LabelProbability {
+                label: label.name.clone(),
+                probability: (log_prob - log_denominator).exp(),
+            })
+            .collect()
+    }
+
+    /// Rescale label probabilities so that they agree with a spam probability
+    ///
+    /// The ham label gets `1 - spam_probability`, and the spam labels share
+    /// `spam_probability` in proportion to their own probabilities. Labels
+    /// then follow the scoring mode, the calibration and the domain list or
+    /// rule overrides that set the spam probability. Models without a ham
+    /// label are left as is.
+    pub(super) fn reconcile_labels(
+        &self,
+        mut labels: Vec<LabelProbability>,
+        spam_probability: f64,
+    ) -> Vec<LabelProbability> {
+        if let Some(ham_label) = self.ham_label() {
+            let spam_labels = labels.iter().filter(|label| label.label != ham_label);
+            let spam_count = spam_labels.clone().count();
+            let spam_total: f64 = spam_labels.map(|label| label.probability).sum();
+
+            for label in labels.iter_mut() {
+                label.probability = if label.label == ham_label {
+                    1.0 - spam_probability
+                } else if spam_total > 0.0 {
+                    spam_probability * label.probability / spam_total
+                } else {
+                    spam_probability / spam_count as f64
+                };
+            }
+        }
+        sort_by_probability(labels)
+    }
+
+    /// Label of a decision: the ham label, or the most likely spam label when `is_spam`
+    pub(super) fn decided_label(&self, labels: &[LabelProbability], is_spam: bool) -> String {
+        let ham_label = self.ham_label();
+        labels
+            .iter()
+            .find(|label| (Some(label.label.as_str()) != ham_label) == is_spam)
+            .or(labels.first())
+            .map(|label| label.label.clone())
+            .unwrap_or_default()
+    }
+
+    /// Label standing for legitimate messages, `None` for multi-class models without one
+    fn ham_label(&self) -> Option<&str> {
+        match &self.labels {
+            Some(table) => table.ham_label(),
+            None => Some(LABEL_HAM),
+        }
+    }
+}
+
+fn sort_by_probability(mut probabilities: Vec<LabelProbability>) -> Vec<LabelProbability> {
+    probabilities.sort_by(|left, right| right.probability.total_cmp(&left.probability));
+    probabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::super::model::ModelHeader;
+    use super::*;
+
+    fn label(name: &str, documents: u32, tokens: u32) -> LabelStats {
+        LabelStats {
+            name: name.to_string(),
+            documents,
+            tokens,
+        }
+    }
+
+    /// Three-label fixture: "account" is phishing, "sale" promotional, "lunch" legitimate
+    fn build_model() -> Vec<u8> {
+        #[rustfmt::skip]
+        let counts = [
+            // phishing, promotional, legitimate
+            9, 1, 1, // account
+            0, 1, 8, // lunch
+            1, 9, 1, // sale
+        ];
+
+        let header = ModelHeader {
+            spam_documents: 6,
+            ham_documents: 3,
+            spam_tokens: 21,
+            ham_tokens: 10,
+            vocabulary_size: 3,
+            labels: vec![
+                label("phishing", 3, 10),
+                label("promotional", 3, 11),
+                label("legitimate", 3, 10),
+            ],
+            ham_label: "legitimate".to_string(),
+            ..Default::default()
+        };
+
+        let rows = [("account", 0), ("lunch", 1), ("sale", 2)];
+        fixtures::write_model(&header, &rows, &counts)
+    }
+
+    #[test]
+    fn test_label_table_counter() {
+        let table = LabelTable::new(
+            vec![label("phishing", 0, 0), label("legitimate", 0, 0)],
+            "legitimate",
+            [1u32, 2, 3, 4]
+                .iter()
+                .flat_map(|count| count.to_le_bytes())
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(table.count(1, 0), 3);
+        let counter = table.counter(1);
+        assert_eq!((counter.spam, counter.ham), (3, 4));
+    }
+
+    #[test]
+    fn test_classify_labels() {
+        let data = build_model();
+        let classifier = NaiveBayesClassifier::from_bytes(&data).unwrap();
+
+        let labels = classifier.classify_labels("account account sale");
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels[0].label, "phishing");
+        let sum: f64 = labels.iter().map(|label| label.probability).sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+
+        assert_eq!(
+            classifier.classify_labels("sale sale")[0].label,
+            "promotional"
+        );
+        assert_eq!(classifier.classify_labels("lunch")[0].label, "legitimate");
+    }
+
+    #[test]
+    fn test_binary_api_on_multiclass_model() {
+        let data = build_model();
+        let classifier = NaiveBayesClassifier::from_bytes(&data).unwrap();
+
+        let counter = classifier.get_token_counter("account");
+        assert_eq!((counter.spam, counter.ham), (10, 1));
+
+        assert!(classifier.classify("account sale") > 0.5);
+        assert!(classifier.classify("lunch lunch") < 0.5);
+    }
+
+    #[test]
+    fn test_labels_follow_decision() {
+        let data = build_model();
+        let mut classifier = NaiveBayesClassifier::from_bytes(&data).unwrap();
+        classifier.set_mode(super::super::ScoringMode::Complement);
+
+        // Spam labels add up to the spam probability of the scoring mode
+        let result = classifier.classify_detailed("account sale lunch");
+        let spam: f64 = result
+            .labels
+            .iter()
+            .filter(|label| label.label != "legitimate")
+            .map(|label| label.probability)
+            .sum();
+        assert!((spam - result.spam_probability).abs() < 1e-9);
+
+        // Overrides carry over to the labels
+        let rules = super::super::rules::RuleSet::parse(
+            r#"[{"name": "allow", "literal": "account", "verdict": "ham"}]"#,
+        )
+        .unwrap();
+        let result = classifier.classify_detailed("account account sale");
+        assert_eq!(result.label, "phishing");
+        let result = classifier.apply_rules(result, &rules.evaluate("account account sale"));
+        assert!(!result.is_spam);
+        assert_eq!(result.label, "legitimate");
+    }
+}
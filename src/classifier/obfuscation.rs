@@ -0,0 +1,402 @@
+//! Normalization of text obfuscated to get past filters
+//!
+//! Spammers write "V1@gr@", slip Cyrillic letters into Latin words or hide
+//! zero-width characters inside words, so that every variant is a token the
+//! model has never seen. Before segmentation, invisible characters are
+//! stripped, the confusable letters of mixed-script words are mapped to their
+//! Latin skeleton, leetspeak is undone and letter runs are collapsed. Each
+//! kind of obfuscation found also becomes a feature.
+
+/// Key prefix of obfuscation features, which can't clash with alphanumeric tokens
+pub const OBFUSCATION_FEATURE_PREFIX: &str = "obfuscation:";
+
+/// Characters that render as nothing
+const INVISIBLE: &[char] = &[
+    '\u{00AD}', '\u{180E}', '\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{2061}', '\u{2062}',
+    '\u{2063}', '\u{2064}', '\u{FEFF}',
+];
+
+/// Cyrillic and Greek letters looking like Latin ones
+///
+/// Only mapped in words that also have Latin letters, leaving Russian or
+/// Greek text untouched.
+const CONFUSABLES: &[(char, char)] = &[
+    ('а', 'a'),
+    ('е', 'e'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('ѕ', 's'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('һ', 'h'),
+    ('ԁ', 'd'),
+    ('ԛ', 'q'),
+    ('ԝ', 'w'),
+    ('ӏ', 'l'),
+    ('А', 'A'),
+    ('В', 'B'),
+    ('Е', 'E'),
+    ('І', 'I'),
+    ('Ј', 'J'),
+    ('К', 'K'),
+    ('М', 'M'),
+    ('Н', 'H'),
+    ('О', 'O'),
+    ('Р', 'P'),
+    ('С', 'C'),
+    ('Ѕ', 'S'),
+    ('Т', 'T'),
+    ('Х', 'X'),
+    ('У', 'Y'),
+    ('α', 'a'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+    ('Α', 'A'),
+    ('Β', 'B'),
+    ('Ε', 'E'),
+    ('Ζ', 'Z'),
+    ('Η', 'H'),
+    ('Ι', 'I'),
+    ('Κ', 'K'),
+    ('Μ', 'M'),
+    ('Ν', 'N'),
+    ('Ο', 'O'),
+    ('Ρ', 'P'),
+    ('Τ', 'T'),
+    ('Υ', 'Y'),
+    ('Χ', 'X'),
+];
+
+/// Digits and symbols standing for letters
+const LEETSPEAK: &[(char, char)] = &[
+    ('0', 'o'),
+    ('1', 'i'),
+    ('3', 'e'),
+    ('4', 'a'),
+    ('5', 's'),
+    ('7', 't'),
+    ('@', 'a'),
+    ('$', 's'),
+];
+
+/// Letters repeated more than this are collapsed, as in "freeeee"
+const MAX_REPEATED_LETTERS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obfuscation {
+    /// Zero-width characters inside words
+    Invisible,
+    /// Fullwidth letters, or lookalike letters mixed with Latin ones
+    Confusable,
+    /// Digits or symbols between letters
+    Leetspeak,
+    /// Runs of the same letter
+    Repeated,
+}
+
+impl Obfuscation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Invisible => "invisible",
+            Self::Confusable => "confusable",
+            Self::Leetspeak => "leetspeak",
+            Self::Repeated => "repeated",
+        }
+    }
+
+    /// Feature token recording the obfuscation
+    pub fn token(self) -> String {
+        format!("{OBFUSCATION_FEATURE_PREFIX}{}", self.name())
+    }
+}
+
+/// First occurrence of a kind of obfuscation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: Obfuscation,
+    /// Character offset of the obfuscated word start in the original text
+    pub start: usize,
+    /// Character offset of the obfuscated word end in the original text
+    pub end: usize,
+}
+
+/// Text with its obfuscation undone
+#[derive(Debug, Clone)]
+pub struct Normalized {
+    pub text: String,
+    /// Character offset in the original text of each character, then of the text end
+    origins: Vec<usize>,
+    /// Each kind of obfuscation found
+    pub findings: Vec<Finding>,
+}
+
+impl Normalized {
+    pub fn new(input: &str) -> Self {
+        let chars: Vec<char> = input.chars().collect();
+        let mut normalized = Self {
+            text: String::with_capacity(input.len()),
+            origins: Vec::with_capacity(chars.len() + 1),
+            findings: Vec::new(),
+        };
+
+        // Characters with their original offsets, without invisible characters
+        // inside words. Others are kept, as they join emoji sequences.
+        let mut visible: Vec<(char, usize)> = Vec::with_capacity(chars.len());
+        for (index, &c) in chars.iter().enumerate() {
+            if INVISIBLE.contains(&c) {
+                let after = chars[index + 1..].iter().find(|c| !INVISIBLE.contains(c));
+                let inside_word = visible.last().is_some_and(|(c, _)| c.is_alphanumeric())
+                    && after.is_some_and(|c| c.is_alphanumeric());
+                if inside_word {
+                    normalized.record(Obfuscation::Invisible, index, index + 1);
+                    continue;
+                }
+            }
+            visible.push((c, index));
+        }
+
+        let mut cursor = 0;
+        while cursor < visible.len() {
+            let (c, origin) = visible[cursor];
+            if !is_word_char(c) {
+                normalized.push(c, origin);
+                cursor += 1;
+                continue;
+            }
+
+            let length = visible[cursor..]
+                .iter()
+                .position(|&(c, _)| !is_word_char(c))
+                .unwrap_or(visible.len() - cursor);
+            let word = &visible[cursor..cursor + length];
+            let next = &visible[cursor + length..];
+            normalized.push_word(word, next);
+            cursor += length;
+        }
+
+        normalized.origins.push(chars.len());
+        normalized
+    }
+
+    /// Character offset in the original text of a character offset in the normalized text
+    pub fn origin(&self, index: usize) -> usize {
+        self.origins[index]
+    }
+
+    fn push(&mut self, c: char, origin: usize) {
+        self.text.push(c);
+        self.origins.push(origin);
+    }
+
+    fn record(&mut self, kind: Obfuscation, start: usize, end: usize) {
+        if !self.findings.iter().any(|finding| finding.kind == kind) {
+            self.findings.push(Finding { kind, start, end });
+        }
+    }
+
+    /// Push a word along with the characters following it
+    fn push_word(&mut self, word: &[(char, usize)], next: &[(char, usize)]) {
+        let (start, end) = (word[0].1, word[word.len() - 1].1 + 1);
+        let mut chars: Vec<char> = word.iter().map(|&(c, _)| c).collect();
+
+        // Fullwidth forms, as in "ＦＲＥＥ"
+        for c in chars.iter_mut() {
+            if let Some(ascii) = fullwidth(*c) {
+                *c = ascii;
+                self.record(Obfuscation::Confusable, start, end);
+            }
+        }
+
+        if chars.iter().any(char::is_ascii_alphabetic) {
+            for c in chars.iter_mut() {
+                if let Some(&(_, latin)) =
+                    CONFUSABLES.iter().find(|(confusable, _)| confusable == c)
+                {
+                    *c = latin;
+                    self.record(Obfuscation::Confusable, start, end);
+                }
+            }
+        }
+
+        if undo_leetspeak(&mut chars, next) {
+            self.record(Obfuscation::Leetspeak, start, end);
+        }
+
+        let mut run = 0;
+        for (index, &c) in chars.iter().enumerate() {
+            let repeated = index > 0 && c.to_lowercase().eq(chars[index - 1].to_lowercase());
+            run = if repeated && c.is_alphabetic() {
+                run + 1
+            } else {
+                1
+            };
+
+            // Dropped letters belong to the last one kept
+            if run > MAX_REPEATED_LETTERS {
+                self.record(Obfuscation::Repeated, start, end);
+            } else {
+                self.push(c, word[index].1);
+            }
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || LEETSPEAK.iter().any(|&(leet, _)| leet == c)
+}
+
+fn fullwidth(c: char) -> Option<char> {
+    match c {
+        '０'..='９' | 'Ａ'..='Ｚ' | 'ａ'..='ｚ' => char::from_u32(c as u32 - 0xFEE0),
+        _ => None,
+    }
+}
+
+/// Replace the leetspeak of a word, returning whether there was any
+///
+/// Digits and symbols are only read as letters between the first and last
+/// letters of the word, along with symbols right after it, as in "V1@gr@".
+/// Words like "mp3" or "covid19" are left as is, and so are email addresses.
+///
+/// Names mixing letters and digits, like "iPhone15Pro", "W3C" or "B2B4U", are
+/// left as is too: a word is only read as leetspeak when its digits all map
+/// to letters and stand alone, and when it has a `@` or `$` symbol or at
+/// least two such digits.
+fn undo_leetspeak(chars: &mut [char], next: &[(char, usize)]) -> bool {
+    let letters = |c: &char| c.is_alphabetic();
+    let (Some(first), Some(last)) = (
+        chars.iter().position(letters),
+        chars.iter().rposition(letters),
+    ) else {
+        return false;
+    };
+
+    let leet = |c: char| {
+        LEETSPEAK
+            .iter()
+            .find(|&&(symbol, _)| symbol == c)
+            .map(|&(_, letter)| letter)
+    };
+    if !chars[first..last].iter().any(|&c| leet(c).is_some()) {
+        return false;
+    }
+
+    let inner = &chars[first..=last];
+    let digits = inner.iter().filter(|c| c.is_ascii_digit()).count();
+    let unmapped = inner
+        .iter()
+        .any(|&c| c.is_ascii_digit() && leet(c).is_none());
+    let runs = inner
+        .windows(2)
+        .any(|pair| pair.iter().all(char::is_ascii_digit));
+    let symbols = inner.iter().any(|c| matches!(c, '@' | '$'));
+    if unmapped || runs || (!symbols && digits < 2) {
+        return false;
+    }
+
+    let email =
+        chars.contains(&'@') && matches!(next, [('.', _), (c, _), ..] if c.is_alphanumeric());
+    if email {
+        return false;
+    }
+
+    let trailing = chars[last + 1..]
+        .iter()
+        .take_while(|c| !c.is_ascii_digit())
+        .count();
+    for c in chars[first..=last + trailing].iter_mut() {
+        if let Some(letter) = leet(*c) {
+            *c = letter;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<Obfuscation> {
+        Normalized::new(input)
+            .findings
+            .iter()
+            .map(|finding| finding.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize() {
+        for (input, expected, kind) in [
+            ("V1@gr@ now", "Viagra now", Obfuscation::Leetspeak),
+            ("p@$$w0rd", "password", Obfuscation::Leetspeak),
+            ("Fr33 m0n3y", "Fr33 money", Obfuscation::Leetspeak),
+            (
+                "fr\u{0435}\u{0435}\u{0435}\u{0435}",
+                "free",
+                Obfuscation::Confusable,
+            ),
+            ("ＦＲＥＥ", "FREE", Obfuscation::Confusable),
+            ("cl\u{200B}ick here", "click here", Obfuscation::Invisible),
+            ("Freeeeee!!!", "Free!!!", Obfuscation::Repeated),
+        ] {
+            let normalized = Normalized::new(input);
+            assert_eq!(normalized.text, expected, "{input}");
+            assert!(kinds(input).contains(&kind), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_untouched() {
+        for input in [
+            "Call me at 555-0199 about the $1000 offer",
+            "mp3 covid19 4you",
+            "iPhone15Pro MP3s W3C B2B4U",
+            "Write to win@prize.biz today",
+            "Привет, как дела?",
+            "\u{FEFF}Hello 👨\u{200D}👩\u{200D}👧",
+            "Good cool book",
+        ] {
+            let normalized = Normalized::new(input);
+            assert_eq!(normalized.text, input, "{input}");
+            assert!(normalized.findings.is_empty(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_origins() {
+        let input = "Sooooo fr\u{200B}ee";
+        let normalized = Normalized::new(input);
+        assert_eq!(normalized.text, "Soo free");
+
+        // Collapsed and invisible characters belong to the character before
+        assert_eq!(normalized.origin(3), 6);
+        assert_eq!(normalized.origin(6), 10);
+        assert_eq!(normalized.origin(8), input.chars().count());
+
+        let findings = normalized.findings;
+        assert_eq!(
+            findings,
+            [
+                Finding {
+                    kind: Obfuscation::Invisible,
+                    start: 9,
+                    end: 10
+                },
+                Finding {
+                    kind: Obfuscation::Repeated,
+                    start: 0,
+                    end: 6
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Placeholder tokens for money amounts, phone numbers, emails and numbers
+//!
+//! Specific values like "$1000000" or "+1-800-555-0199" are unique tokens,
+//! or are dropped by word segmentation, and carry no signal the model can
+//! learn. They are replaced before segmentation with tokens naming their
+//! class, where they stand, so that the model generalizes across values and
+//! n-grams span them. The domain of email addresses, a useful signal, becomes
+//! a feature of its own.
+
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)[a-z0-9][a-z0-9._%+-]*@[a-z0-9](?:[a-z0-9-]*[a-z0-9])?(?:\.[a-z0-9](?:[a-z0-9-]*[a-z0-9])?)*\.[a-z]{2,}").unwrap()
+});
+
+/// Amounts with a currency symbol or code on either side, as in "$1,000" or "50 EUR"
+static MONEY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?i)(?:[$€£¥₹₽₿]|\b(?:usd|eur|gbp)\b)\s?[0-9](?:[0-9,.]*[0-9])?(?:\s?(?:k|m|bn|million|billion)\b)?",
+        r"|[0-9](?:[0-9,.]*[0-9])?\s?(?:[$€£¥₹₽₿]|(?:usd|eur|gbp|dollars?|euros?|pounds?|bucks)\b)",
+    ))
+    .unwrap()
+});
+
+/// Candidate phone numbers, validated by [`is_phone`]
+static PHONE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\+?\(?[0-9][0-9 ().-]{5,}[0-9]").unwrap());
+
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[0-9](?:[0-9,.]*[0-9])?").unwrap());
+
+/// Key prefix of email domain features, which can't clash with alphanumeric tokens
+pub const EMAIL_DOMAIN_PREFIX: &str = "emaildomain:";
+
+/// Fewest and most digits of phone numbers, as in E.164
+const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 7..=15;
+
+/// Fewest digits in the last group of phone numbers without a country code
+const MIN_PHONE_LAST_GROUP: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderKind {
+    Email,
+    Money,
+    Phone,
+    /// Number with its count of digits
+    Number(usize),
+}
+
+/// A value of the input replaced with a placeholder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    pub kind: PlaceholderKind,
+    /// Character offset of the value start in the input
+    pub start: usize,
+    /// Character offset of the value end in the input
+    pub end: usize,
+    /// Lowercased domain of email addresses
+    pub domain: Option<String>,
+}
+
+impl Placeholder {
+    pub fn token(&self) -> String {
+        match self.kind {
+            PlaceholderKind::Email => "__email__".to_string(),
+            PlaceholderKind::Money => "__money__".to_string(),
+            PlaceholderKind::Phone => "__phone__".to_string(),
+            PlaceholderKind::Number(digits) => format!("__number_{digits}digits__"),
+        }
+    }
+
+    /// Feature emitted along with the placeholder, the domain of email addresses
+    pub fn feature(&self) -> Option<String> {
+        self.domain
+            .as_ref()
+            .map(|domain| format!("{EMAIL_DOMAIN_PREFIX}{domain}"))
+    }
+}
+
+/// Text with its values replaced with placeholder tokens
+#[derive(Debug, Clone)]
+pub struct Replaced<'a> {
+    pub text: Cow<'a, str>,
+    /// Character offset in the input of each character, then of the text end,
+    /// empty when nothing was replaced
+    origins: Vec<usize>,
+    pub placeholders: Vec<Placeholder>,
+}
+
+impl<'a> Replaced<'a> {
+    /// Input left as is
+    pub fn unchanged(input: &'a str) -> Self {
+        Self {
+            text: Cow::Borrowed(input),
+            origins: Vec::new(),
+            placeholders: Vec::new(),
+        }
+    }
+
+    /// Character offset in the input of a character offset in the text
+    ///
+    /// Placeholder tokens start at the start of their value, and end at its end.
+    pub fn origin(&self, index: usize) -> usize {
+        self.origins.get(index).copied().unwrap_or(index)
+    }
+}
+
+/// Replace the values of a text with placeholder tokens
+pub fn replace(input: &str) -> Replaced<'_> {
+    let placeholders = extract(input);
+    if placeholders.is_empty() {
+        return Replaced::unchanged(input);
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let mut text = String::with_capacity(input.len());
+    let mut origins = Vec::with_capacity(chars.len() + 1);
+    let mut values = placeholders.iter().peekable();
+    let mut index = 0;
+
+    while index < chars.len() {
+        match values.next_if(|value| value.start == index) {
+            Some(value) => {
+                for c in value.token().chars() {
+                    text.push(c);
+                    origins.push(index);
+                }
+                index = value.end;
+            }
+            None => {
+                text.push(chars[index]);
+                origins.push(index);
+                index += 1;
+            }
+        }
+    }
+    origins.push(chars.len());
+
+    Replaced {
+        text: Cow::Owned(text),
+        origins,
+        placeholders,
+    }
+}
+
+/// Find the values of a text to replace with placeholders, in order
+///
+/// Emails take precedence over money amounts, then phone numbers, then
+/// numbers. Values must stand on their own, so that "mp3" or "covid19" are
+/// left to word segmentation.
+pub fn extract(input: &str) -> Vec<Placeholder> {
+    // Byte ranges of the values found so far
+    let mut spans: Vec<(usize, usize, PlaceholderKind)> = Vec::new();
+    let mut add = |start: usize, end: usize, kind: PlaceholderKind| {
+        let overlaps = spans.iter().any(|&(s, e, _)| start < e && s < end);
+        if !overlaps && stands_alone(input, start, end) {
+            spans.push((start, end, kind));
+        }
+    };
+
+    for found in EMAIL.find_iter(input) {
+        add(found.start(), found.end(), PlaceholderKind::Email);
+    }
+    for found in MONEY.find_iter(input) {
+        add(found.start(), found.end(), PlaceholderKind::Money);
+    }
+    for found in PHONE.find_iter(input) {
+        if is_phone(found.as_str()) {
+            add(found.start(), found.end(), PlaceholderKind::Phone);
+        }
+    }
+    for found in NUMBER.find_iter(input) {
+        let digits = found.as_str().chars().filter(char::is_ascii_digit).count();
+        add(found.start(), found.end(), PlaceholderKind::Number(digits));
+    }
+
+    spans.sort_by_key(|&(start, _, _)| start);
+
+    let mut placeholders = Vec::with_capacity(spans.len());
+    let (mut byte_cursor, mut char_cursor) = (0, 0);
+    for (start, end, kind) in spans {
+        let start_char = char_cursor + input[byte_cursor..start].chars().count();
+        let end_char = start_char + input[start..end].chars().count();
+        (byte_cursor, char_cursor) = (end, end_char);
+
+        let domain = match kind {
+            PlaceholderKind::Email => input[start..end]
+                .split_once('@')
+                .map(|(_, domain)| domain.to_lowercase()),
+            _ => None,
+        };
+        placeholders.push(Placeholder {
+            kind,
+            start: start_char,
+            end: end_char,
+            domain,
+        });
+    }
+    placeholders
+}
+
+/// Whether the byte range isn't preceded or followed by a letter, digit or underscore
+fn stands_alone(input: &str, start: usize, end: usize) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '_';
+    let before = input[..start].chars().next_back().is_some_and(word);
+    let after = input[end..].chars().next().is_some_and(word);
+    !before && !after
+}
+
+/// Whether a candidate is a phone number rather than a date or a plain number
+///
+/// Numbers with a country code are phone numbers, others need separated
+/// groups ending with a long enough one, as in "555-0199" but not "2024-01-15".
+fn is_phone(candidate: &str) -> bool {
+    let groups: Vec<_> = candidate
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .collect();
+    let digits: usize = groups.iter().map(|group| group.len()).sum();
+    if !PHONE_DIGITS.contains(&digits) {
+        return false;
+    }
+
+    candidate.starts_with('+')
+        || (groups.len() >= 2
+            && groups
+                .last()
+                .is_some_and(|group| group.len() >= MIN_PHONE_LAST_GROUP))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<String> {
+        extract(input).iter().map(Placeholder::token).collect()
+    }
+
+    #[test]
+    fn test_extract() {
+        let text =
+            "Win $1,000,000 now! Call +1-800-555-0199 or write to win@prize.biz, code 123456.";
+        let placeholders = extract(text);
+
+        assert_eq!(
+            tokens(text),
+            ["__money__", "__phone__", "__email__", "__number_6digits__"]
+        );
+
+        let chars: Vec<char> = text.chars().collect();
+        let values: Vec<String> = placeholders
+            .iter()
+            .map(|placeholder| chars[placeholder.start..placeholder.end].iter().collect())
+            .collect();
+        assert_eq!(
+            values,
+            ["$1,000,000", "+1-800-555-0199", "win@prize.biz", "123456"]
+        );
+        assert_eq!(
+            placeholders[2].feature().as_deref(),
+            Some("emaildomain:prize.biz")
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        let text = "Só $500 to Win@Prize.biz";
+        let replaced = replace(text);
+        assert_eq!(replaced.text, "Só __money__ to __email__");
+
+        // Placeholder tokens map back to their values
+        assert_eq!(replaced.origin(3), 3);
+        assert_eq!(replaced.origin(12), 7);
+        assert_eq!(replaced.origin(16), 11);
+        assert_eq!(replaced.origin(25), text.chars().count());
+
+        let unchanged = replace("nothing to replace");
+        assert!(matches!(unchanged.text, Cow::Borrowed(_)));
+        assert_eq!(unchanged.origin(4), 4);
+    }
+
+    #[test]
+    fn test_kinds() {
+        for (input, expected) in [
+            ("Only 50 EUR", "__money__"),
+            ("Earn €5k a week", "__money__"),
+            ("Worth 200 dollars", "__money__"),
+            ("Call (555) 123-4567", "__phone__"),
+            ("Call 555-0199", "__phone__"),
+            ("Née le 2024-01-15", "__number_4digits__"),
+            ("Pi is 3.14", "__number_3digits__"),
+        ] {
+            assert_eq!(tokens(input)[0], expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_words_left_alone() {
+        for input in ["mp3 covid19 4you V1@gr@", "no numbers here", "a_1 b2b"] {
+            assert!(extract(input).is_empty(), "{input}");
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Operator rules layered on top of the Bayes score
+//!
+//! Rules are regex or literal patterns, configured as a JSON array in the
+//! component settings, so that emerging campaigns can be acted upon without
+//! retraining the model. A matching rule either shifts the log-odds of the
+//! score, or forces the verdict.
+//!
+//! ```json
+//! [
+//!   {"name": "crypto-doubling", "regex": "double your (btc|bitcoin)", "score": 3.0},
+//!   {"name": "invoice-scam", "literal": "Invoice attached, pay within 24h", "verdict": "spam"}
+//! ]
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use anyhow::Result;
+
+use super::decision::Verdict;
+
+/// Maximum number of compiled rule sets kept, the cache being cleared when full
+const MAX_COMPILED: usize = 16;
+
+/// Rule sets compiled by the instance, by source
+///
+/// Settings rarely change between the requests of a component instance, so
+/// rules end up being compiled once per instance.
+static COMPILED: LazyLock<Mutex<HashMap<String, Arc<RuleSet>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Rule as configured, before compilation
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSpec {
+    pub name: String,
+    /// Text matched as is
+    pub literal: Option<String>,
+    /// Regular expression, in the syntax of the `regex` crate
+    pub regex: Option<String>,
+    /// Patterns are case-insensitive unless set
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Log-odds added to the score, positive values pushing towards spam
+    pub score: Option<f64>,
+    /// Verdict forced by the rule
+    pub verdict: Option<Verdict>,
+}
+
+/// Effect of a matching rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleAction {
+    Score(f64),
+    Verdict(Verdict),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub action: RuleAction,
+    pattern: regex::Regex,
+}
+
+/// Rule that matched an input
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RuleHit {
+    pub rule: String,
+    /// Text of the first match
+    pub matched: String,
+    /// Character offset of the first match start in the matched text
+    pub start: usize,
+    /// Character offset of the first match end in the matched text
+    pub end: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<Verdict>,
+}
+
+impl Rule {
+    pub fn compile(spec: RuleSpec) -> Result<Self> {
+        let name = spec.name;
+        if name.trim().is_empty() {
+            anyhow::bail!("Rules should have a name");
+        }
+
+        let pattern = match (spec.literal, spec.regex) {
+            (Some(literal), None) if !literal.is_empty() => regex::escape(&literal),
+            (None, Some(regex)) if !regex.is_empty() => regex,
+            _ => anyhow::bail!("Rule {name} should have either a literal or a regex pattern"),
+        };
+        let pattern = regex::RegexBuilder::new(&pattern)
+            .case_insensitive(!spec.case_sensitive)
+            .build()
+            .map_err(|err| anyhow::anyhow!("Rule {name} has an invalid regex: {err}"))?;
+
+        let action = match (spec.score, spec.verdict) {
+            (Some(score), None) if score.is_finite() => RuleAction::Score(score),
+            (None, Some(verdict)) => RuleAction::Verdict(verdict),
+            _ => anyhow::bail!("Rule {name} should have either a finite score or a verdict"),
+        };
+
+        Ok(Self {
+            name,
+            action,
+            pattern,
+        })
+    }
+
+    /// Hit of the rule on a text, at its first match
+    pub fn evaluate(&self, text: &str) -> Option<RuleHit> {
+        let found = self.pattern.find(text)?;
+        let start = text[..found.start()].chars().count();
+
+        let (score, verdict) = match self.action {
+            RuleAction::Score(score) => (Some(score), None),
+            RuleAction::Verdict(verdict) => (None, Some(verdict)),
+        };
+        Some(RuleHit {
+            rule: self.name.clone(),
+            matched: found.as_str().to_string(),
+            start,
+            end: start + found.as_str().chars().count(),
+            score,
+            verdict,
+        })
+    }
+}
+
+/// Rules in configuration order
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Compile a JSON array of rules
+    pub fn parse(source: &str) -> Result<Self> {
+        let specs: Vec<RuleSpec> =
+            serde_json::from_str(source).map_err(|err| anyhow::anyhow!("Invalid rules: {err}"))?;
+        Self::compile(specs)
+    }
+
+    pub fn compile(specs: Vec<RuleSpec>) -> Result<Self> {
+        let mut rules: Vec<Rule> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let rule = Rule::compile(spec)?;
+            if rules.iter().any(|known| known.name == rule.name) {
+                anyhow::bail!("Rule {} is defined more than once", rule.name);
+            }
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Compile a JSON array of rules, reusing the rule set compiled earlier from the same source
+    pub fn shared(source: &str) -> Result<Arc<Self>> {
+        let mut compiled = COMPILED.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(rules) = compiled.get(source) {
+            return Ok(rules.clone());
+        }
+
+        let rules = Arc::new(Self::parse(source)?);
+        if compiled.len() >= MAX_COMPILED {
+            compiled.clear();
+        }
+        compiled.insert(source.to_string(), rules.clone());
+        Ok(rules)
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Hits of every rule matching the text, in configuration order
+    pub fn evaluate(&self, text: &str) -> Vec<RuleHit> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.evaluate(text))
+            .collect()
+    }
+}
+
+/// Sum of the score adjustments of the hits
+pub fn total_score(hits: &[RuleHit]) -> f64 {
+    hits.iter().filter_map(|hit| hit.score).sum()
+}
+
+/// Verdict forced by the hits, the first rule with a verdict taking precedence
+pub fn forced_verdict(hits: &[RuleHit]) -> Option<Verdict> {
+    hits.iter().find_map(|hit| hit.verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(source: serde_json::Value) -> Result<RuleSet> {
+        RuleSet::parse(&source.to_string())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = rules(serde_json::json!([
+            {"name": "crypto", "regex": r"double your (btc|bitcoin)", "score": 3.0},
+            {"name": "invoice", "literal": "pay (now)", "verdict": "spam"},
+            {"name": "exact", "literal": "URGENT", "case_sensitive": true, "score": 1.0},
+            {"name": "newsletter", "literal": "unsubscribe", "score": -2.0},
+        ]))
+        .unwrap();
+
+        let hits = rules.evaluate("Été: Double your Bitcoin today, pay (NOW)! urgent");
+        assert_eq!(
+            hits.iter().map(|hit| hit.rule.as_str()).collect::<Vec<_>>(),
+            ["crypto", "invoice"]
+        );
+        assert_eq!(hits[0].matched, "Double your Bitcoin");
+        assert_eq!((hits[0].start, hits[0].end), (5, 24));
+
+        assert_eq!(total_score(&hits), 3.0);
+        assert_eq!(forced_verdict(&hits), Some(Verdict::Spam));
+        assert!(rules.evaluate("Hello").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for (source, error) in [
+            (
+                serde_json::json!([{"name": "a", "score": 1.0}]),
+                "literal or a regex",
+            ),
+            (
+                serde_json::json!([{"name": "a", "literal": "x", "regex": "y", "score": 1.0}]),
+                "literal or a regex",
+            ),
+            (
+                serde_json::json!([{"name": "a", "regex": "(", "score": 1.0}]),
+                "invalid regex",
+            ),
+            (
+                serde_json::json!([{"name": "a", "literal": "x"}]),
+                "score or a verdict",
+            ),
+            (
+                serde_json::json!([{"name": "a", "literal": "x", "verdict": "maybe"}]),
+                "Invalid rules",
+            ),
+            (
+                serde_json::json!([
+                    {"name": "a", "literal": "x", "score": 1.0},
+                    {"name": "a", "literal": "y", "score": 1.0},
+                ]),
+                "more than once",
+            ),
+            (
+                serde_json::json!([{"name": "a", "literal": "x", "weight": 1.0}]),
+                "Invalid rules",
+            ),
+        ] {
+            let err = rules(source).unwrap_err().to_string();
+            assert!(err.contains(error), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_shared() {
+        let source = r#"[{"name": "a", "literal": "x", "score": 1.0}]"#;
+        let first = RuleSet::shared(source).unwrap();
+        let second = RuleSet::shared(source).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(RuleSet::shared("[").is_err());
+    }
+}
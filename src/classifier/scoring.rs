@@ -0,0 +1,416 @@
+//! Naive Bayes scoring modes
+//!
+//! Every mode works on the same per-token spam/ham counters, only the way they
+//! are turned into class scores differs.
+
+use std::collections::{HashMap, HashSet};
+
+use fst::Streamer;
+
+use super::chi_squared::MIN_PROB_STRENGTH;
+use super::{Counter, NaiveBayesClassifier};
+
+/// Algorithm used to score a text against the model counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringMode {
+    /// Multinomial Naive Bayes with Laplace smoothing
+    #[default]
+    Multinomial,
+    /// Complement Naive Bayes, estimating each class from the other class counts
+    ///
+    /// Term frequencies are log-scaled and length-normalized, and the class
+    /// priors are left out, which makes it less sensitive to imbalanced data.
+    Complement,
+    /// Bernoulli Naive Bayes, only considering the presence of each token
+    ///
+    /// Repeating a token doesn't change the score, which resists keyword
+    /// stuffing.
+    Bernoulli,
+    /// Robinson's token probabilities combined with Fisher's chi-squared method
+    ///
+    /// Scores are less extreme than Naive Bayes probabilities and come with a
+    /// ham/unsure/spam verdict.
+    ChiSquared,
+    /// Graham's combining of the most interesting tokens only
+    ///
+    /// Neutral tokens are left out, which resists padding spam with innocent
+    /// text.
+    Graham,
+}
+
+impl std::str::FromStr for ScoringMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "multinomial" => Ok(Self::Multinomial),
+            "complement" => Ok(Self::Complement),
+            "bernoulli" => Ok(Self::Bernoulli),
+            "chi_squared" => Ok(Self::ChiSquared),
+            "graham" => Ok(Self::Graham),
+            _ => anyhow::bail!("Unknown scoring mode: {value}"),
+        }
+    }
+}
+
+impl<D: AsRef<[u8]>> NaiveBayesClassifier<D> {
+    /// Calculate the complement scores of each class
+    ///
+    /// Each class is scored with the likelihoods of its complement, negated so
+    /// that higher values still lean towards the class. With two classes the
+    /// complement of spam is ham and the other way around.
+    pub(super) fn complement_log_probabilities(&self, tokens: &[String]) -> (f64, f64) {
+        let (mut spam_score, mut ham_score) = (0.0, 0.0);
+        for (token, weight) in complement_weights(tokens) {
+            let counter = self.get_token_counter(token);
+            let (p_word_spam, p_word_ham) = self.calculate_likelihoods(&counter);
+
+            spam_score -= weight * p_word_ham.ln();
+            ham_score -= weight * p_word_spam.ln();
+        }
+
+        (spam_score, ham_score)
+    }
+
+    /// Calculate the contribution of each distinct token to the log-odds of the scoring mode
+    ///
+    /// Contributions add up to the log-odds on top of what the mode scores
+    /// without any token, such as the class priors. The chi-squared mode
+    /// doesn't add token evidences up, each token gets the log-odds of its
+    /// Robinson probability instead, or 0 when it is too neutral to count.
+    pub(super) fn token_log_odds<'t>(&self, tokens: &'t [String]) -> HashMap<&'t str, f64> {
+        let mut contributions: HashMap<&str, f64> = HashMap::new();
+        match self.mode {
+            ScoringMode::Multinomial => {
+                for token in tokens {
+                    let counter = self.get_token_counter(token);
+                    let (p_word_spam, p_word_ham) = self.calculate_likelihoods(&counter);
+                    *contributions.entry(token).or_default() += p_word_spam.ln() - p_word_ham.ln();
+                }
+            }
+            ScoringMode::Complement => {
+                for (token, weight) in complement_weights(tokens) {
+                    let counter = self.get_token_counter(token);
+                    let (p_word_spam, p_word_ham) = self.calculate_likelihoods(&counter);
+                    contributions.insert(token, weight * (p_word_spam.ln() - p_word_ham.ln()));
+                }
+            }
+            ScoringMode::Bernoulli => {
+                for token in tokens {
+                    let counter = self.get_token_counter(token);
+                    let (p_word_spam, p_word_ham) = self.bernoulli_likelihoods(&counter);
+                    let log_odds =
+                        (p_word_spam.ln() + p_word_spam) - (p_word_ham.ln() + p_word_ham);
+                    contributions.insert(token, log_odds);
+                }
+            }
+            ScoringMode::Graham => {
+                for token in tokens {
+                    contributions.insert(token, 0.0);
+                }
+                for interesting in self.interesting_tokens(tokens) {
+                    let log_odds =
+                        interesting.probability.ln() - (1.0 - interesting.probability).ln();
+                    if let Some(contribution) = contributions.get_mut(interesting.token.as_str()) {
+                        *contribution = log_odds;
+                    }
+                }
+            }
+            ScoringMode::ChiSquared => {
+                for token in tokens {
+                    let probability = self.robinson_probability(&self.get_token_counter(token));
+                    let log_odds = if (probability - 0.5).abs() < MIN_PROB_STRENGTH {
+                        0.0
+                    } else {
+                        probability.ln() - (1.0 - probability).ln()
+                    };
+                    contributions.insert(token, log_odds);
+                }
+            }
+        }
+        contributions
+    }
+
+    /// Calculate the joint log probabilities of the token set with each class
+    ///
+    /// Absent vocabulary tokens contribute `ln(1 - p)`, approximated by `-p`
+    /// so that their sum derives from the class totals without visiting the
+    /// whole vocabulary.
+    pub(super) fn bernoulli_log_probabilities(&self, tokens: &[String]) -> (f64, f64) {
+        let (spam_documents, ham_documents) = self.bernoulli_documents();
+        let smoothing = self.alpha * self.stats.unique_tokens as f64;
+
+        let (spam_total, ham_total) = self.bernoulli_totals;
+
+        let mut log_prob_spam = self.stats.prior_spam().ln()
+            - (spam_total + smoothing) / (spam_documents + 2.0 * self.alpha);
+        let mut log_prob_ham = self.stats.prior_ham().ln()
+            - (ham_total + smoothing) / (ham_documents + 2.0 * self.alpha);
+
+        let mut seen = HashSet::new();
+        for token in tokens.iter().filter(|token| seen.insert(token.as_str())) {
+            let counter = self.get_token_counter(token);
+            let (p_word_spam, p_word_ham) = self.bernoulli_likelihoods(&counter);
+
+            // Swap the token's absent term for its present one
+            log_prob_spam += p_word_spam.ln() + p_word_spam;
+            log_prob_ham += p_word_ham.ln() + p_word_ham;
+        }
+
+        (log_prob_spam, log_prob_ham)
+    }
+
+    /// Calculate the probability of a token appearing in a document of each class
+    ///
+    /// Token counts stand in for document frequencies, capped at the number of
+    /// documents of the class.
+    pub(super) fn bernoulli_likelihoods(&self, counter: &Counter) -> (f64, f64) {
+        let (spam_documents, ham_documents) = self.bernoulli_documents();
+
+        let spam_likelihood = ((counter.spam as f64).min(spam_documents) + self.alpha)
+            / (spam_documents + 2.0 * self.alpha);
+        let ham_likelihood = ((counter.ham as f64).min(ham_documents) + self.alpha)
+            / (ham_documents + 2.0 * self.alpha);
+
+        (spam_likelihood, ham_likelihood)
+    }
+
+    /// Sum the token counts of the whole vocabulary, capped as in [`Self::bernoulli_likelihoods`]
+    ///
+    /// The absent-token terms then cancel out exactly with the per-token
+    /// corrections. Every key of the model is visited, so this is only done
+    /// when loading it.
+    pub(super) fn capped_totals(&self) -> (f64, f64) {
+        let (spam_documents, ham_documents) = self.bernoulli_documents();
+        let (mut spam_total, mut ham_total) = (0.0, 0.0);
+
+        let mut stream = self.model.stream();
+        while let Some((_, value)) = stream.next() {
+            let counter = match &self.labels {
+                Some(labels) => labels.counter(value),
+                None => Counter::from_u64(value),
+            };
+            spam_total += (counter.spam as f64).min(spam_documents);
+            ham_total += (counter.ham as f64).min(ham_documents);
+        }
+
+        (spam_total, ham_total)
+    }
+
+    /// Number of documents of each class, token totals for legacy models without document counts
+    fn bernoulli_documents(&self) -> (f64, f64) {
+        if self.stats.spam_documents + self.stats.ham_documents > 0 {
+            (
+                self.stats.spam_documents as f64,
+                self.stats.ham_documents as f64,
+            )
+        } else {
+            (self.stats.total_spam as f64, self.stats.total_ham as f64)
+        }
+    }
+}
+
+/// Weight of each distinct token in the complement mode
+///
+/// Term frequencies are log-scaled, then normalized by the document length.
+fn complement_weights(tokens: &[String]) -> Vec<(&str, f64)> {
+    let mut frequencies: HashMap<&str, f64> = HashMap::new();
+    for token in tokens {
+        *frequencies.entry(token.as_str()).or_default() += 1.0;
+    }
+
+    let weights: Vec<_> = frequencies
+        .into_iter()
+        .map(|(token, frequency)| (token, (1.0 + frequency).ln()))
+        .collect();
+    let norm = weights
+        .iter()
+        .map(|(_, weight)| weight * weight)
+        .sum::<f64>()
+        .sqrt();
+
+    weights
+        .into_iter()
+        .map(|(token, weight)| (token, weight / norm))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures;
+    use super::super::model::ModelHeader;
+    use super::*;
+
+    /// Imbalanced fixture: 4 spam documents for 16 ham ones
+    fn build_model() -> Vec<u8> {
+        fixtures::build_model(
+            &[
+                ("free", Counter { spam: 6, ham: 1 }),
+                ("lunch", Counter { spam: 0, ham: 6 }),
+                ("money", Counter { spam: 4, ham: 1 }),
+                ("today", Counter { spam: 1, ham: 4 }),
+            ],
+            ModelHeader {
+                spam_documents: 4,
+                ham_documents: 16,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            "multinomial".parse::<ScoringMode>().unwrap(),
+            ScoringMode::Multinomial
+        );
+        assert_eq!(
+            "complement".parse::<ScoringMode>().unwrap(),
+            ScoringMode::Complement
+        );
+        assert_eq!(
+            "bernoulli".parse::<ScoringMode>().unwrap(),
+            ScoringMode::Bernoulli
+        );
+        assert_eq!(
+            "chi_squared".parse::<ScoringMode>().unwrap(),
+            ScoringMode::ChiSquared
+        );
+        assert_eq!(
+            "graham".parse::<ScoringMode>().unwrap(),
+            ScoringMode::Graham
+        );
+        assert!("gaussian".parse::<ScoringMode>().is_err());
+    }
+
+    #[test]
+    fn test_modes_agree_on_clear_cases() {
+        let data = build_model();
+
+        for mode in [
+            ScoringMode::Multinomial,
+            ScoringMode::Complement,
+            ScoringMode::Bernoulli,
+        ] {
+            let classifier = fixtures::classifier(&data, mode);
+
+            let spam = classifier.classify("free money");
+            let ham = classifier.classify("lunch today");
+            assert!(spam > 0.5, "{mode:?} scored spam {spam}");
+            assert!(ham < 0.5, "{mode:?} scored ham {ham}");
+            assert!(spam > classifier.classify("free lunch"));
+        }
+    }
+
+    #[test]
+    fn test_complement_ignores_priors() {
+        let data = build_model();
+
+        // Without any evidence, only the multinomial mode leans towards the majority class
+        let multinomial = fixtures::classifier(&data, ScoringMode::Multinomial).classify("");
+        assert!((multinomial - 0.2).abs() < 1e-9);
+
+        let complement = fixtures::classifier(&data, ScoringMode::Complement).classify("");
+        assert!((complement - 0.5).abs() < 1e-9);
+
+        // Borderline texts lean further towards spam once the imbalance is ignored
+        assert!(
+            fixtures::classifier(&data, ScoringMode::Complement).classify("free today")
+                > fixtures::classifier(&data, ScoringMode::Multinomial).classify("free today")
+        );
+    }
+
+    #[test]
+    fn test_bernoulli_resists_keyword_stuffing() {
+        let data = build_model();
+        let stuffed = "free free free free free free lunch";
+
+        let bernoulli = fixtures::classifier(&data, ScoringMode::Bernoulli);
+        assert_eq!(
+            bernoulli.classify(stuffed),
+            bernoulli.classify("free lunch")
+        );
+
+        let multinomial = fixtures::classifier(&data, ScoringMode::Multinomial);
+        assert!(multinomial.classify(stuffed) > multinomial.classify("free lunch"));
+    }
+
+    #[test]
+    fn test_bernoulli_absent_terms() {
+        let data = build_model();
+        let classifier = fixtures::classifier(&data, ScoringMode::Bernoulli);
+
+        // With the whole vocabulary present, no absent term is left, even for
+        // "free" whose count is capped at the 4 spam documents
+        let tokens: Vec<String> = ["free", "lunch", "money", "today"].map(String::from).into();
+        let (log_prob_spam, log_prob_ham) = classifier.bernoulli_log_probabilities(&tokens);
+
+        let (mut expected_spam, mut expected_ham) = (
+            classifier.stats.prior_spam().ln(),
+            classifier.stats.prior_ham().ln(),
+        );
+        for token in &tokens {
+            let (p_word_spam, p_word_ham) =
+                classifier.bernoulli_likelihoods(&classifier.get_token_counter(token));
+            expected_spam += p_word_spam.ln();
+            expected_ham += p_word_ham.ln();
+        }
+        assert!((log_prob_spam - expected_spam).abs() < 1e-12);
+        assert!((log_prob_ham - expected_ham).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_explanations_follow_mode() {
+        let data = build_model();
+        let text = "free money free lunch today";
+
+        for mode in [
+            ScoringMode::Multinomial,
+            ScoringMode::Complement,
+            ScoringMode::Bernoulli,
+            ScoringMode::Graham,
+        ] {
+            let classifier = fixtures::classifier(&data, mode);
+            let explanation = classifier.classify_explained(text, 5).explanation.unwrap();
+
+            // Contributions add up to the log-odds on top of those of an empty input
+            let sum: f64 = explanation
+                .tokens
+                .iter()
+                .map(|contribution| contribution.log_likelihood_ratio)
+                .sum();
+            let log_odds = classifier.classify_log_odds(text) - classifier.classify_log_odds("");
+            assert!(
+                (sum - log_odds).abs() < 1e-9,
+                "{mode:?}: {sum} != {log_odds}"
+            );
+            assert!(
+                explanation
+                    .top_spam
+                    .iter()
+                    .any(|contribution| contribution.token == "free"),
+                "{mode:?}"
+            );
+        }
+
+        // Repeats share the contribution of their token when only presence counts
+        let classifier = fixtures::classifier(&data, ScoringMode::Bernoulli);
+        let explanation = classifier.classify_explained(text, 5).explanation.unwrap();
+        let free: Vec<_> = explanation
+            .tokens
+            .iter()
+            .filter(|contribution| contribution.token == "free")
+            .collect();
+        assert_eq!(free[0].log_likelihood_ratio, free[1].log_likelihood_ratio);
+        assert_eq!(
+            free[0].log_likelihood_ratio * 2.0,
+            classifier
+                .classify_explained("free", 5)
+                .explanation
+                .unwrap()
+                .tokens[0]
+                .log_likelihood_ratio
+        );
+    }
+}
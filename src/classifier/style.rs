@@ -0,0 +1,201 @@
+//! Stylometric meta-features
+//!
+//! Lowercasing and keeping alphanumeric tokens only loses strong signals
+//! such as "ALL CAPS", "!!!" or "$$$". They are measured on the raw input
+//! instead, and each measure is emitted as a synthetic token naming its
+//! bucket, so that the model learns their counts like those of words.
+
+/// Key prefix of stylometric features, which can't clash with alphanumeric tokens
+pub const STYLE_FEATURE_PREFIX: &str = "style:";
+
+/// Fewest cased letters for the uppercase ratio to be meaningful
+const MIN_CASED_LETTERS: usize = 5;
+
+/// Shortest run of a punctuation or symbol character counted as a burst, as in "!!!"
+const MIN_BURST_LENGTH: usize = 3;
+
+const CURRENCY_SYMBOLS: &[char] = &[
+    '$', '¢', '£', '¤', '¥', '֏', '฿', '₡', '₦', '₩', '₪', '₫', '€', '₭', '₮', '₱', '₲', '₴', '₸',
+    '₹', '₺', '₼', '₽', '₾', '₿',
+];
+
+/// Measures of the style of an input
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    /// Length in characters, without surrounding whitespace
+    pub length: usize,
+    pub cased_letters: usize,
+    pub uppercase_letters: usize,
+    pub alphanumeric: usize,
+    pub digits: usize,
+    pub exclamations: usize,
+    pub currency: bool,
+    /// Whether a punctuation or symbol character is repeated, as in "!!!" or "$$$"
+    pub burst: bool,
+}
+
+impl Style {
+    pub fn measure(input: &str) -> Self {
+        let input = input.trim();
+        let mut style = Self::default();
+        let mut previous = None;
+        let mut run = 0;
+
+        for c in input.chars() {
+            style.length += 1;
+
+            if c.is_uppercase() {
+                style.cased_letters += 1;
+                style.uppercase_letters += 1;
+            } else if c.is_lowercase() {
+                style.cased_letters += 1;
+            }
+
+            if c.is_alphanumeric() {
+                style.alphanumeric += 1;
+            }
+            if c.is_numeric() {
+                style.digits += 1;
+            }
+            if matches!(c, '!' | '¡') {
+                style.exclamations += 1;
+            }
+            if CURRENCY_SYMBOLS.contains(&c) {
+                style.currency = true;
+            }
+
+            run = if previous == Some(c) { run + 1 } else { 1 };
+            if run >= MIN_BURST_LENGTH && !c.is_alphanumeric() && !c.is_whitespace() {
+                style.burst = true;
+            }
+            previous = Some(c);
+        }
+
+        style
+    }
+
+    /// Feature tokens of the measures, none for blank inputs
+    pub fn tokens(&self) -> Vec<String> {
+        if self.length == 0 {
+            return Vec::new();
+        }
+
+        let mut features = vec![("length", length_bucket(self.length))];
+        if self.cased_letters >= MIN_CASED_LETTERS {
+            let ratio = self.uppercase_letters as f64 / self.cased_letters as f64;
+            features.push(("caps", caps_bucket(ratio)));
+        }
+        if self.alphanumeric > 0 {
+            let ratio = self.digits as f64 / self.alphanumeric as f64;
+            features.push(("digits", digits_bucket(ratio)));
+        }
+        features.push(("exclamations", exclamations_bucket(self.exclamations)));
+
+        let mut tokens: Vec<_> = features
+            .into_iter()
+            .map(|(measure, bucket)| format!("{STYLE_FEATURE_PREFIX}{measure}:{bucket}"))
+            .collect();
+        if self.currency {
+            tokens.push(format!("{STYLE_FEATURE_PREFIX}currency"));
+        }
+        if self.burst {
+            tokens.push(format!("{STYLE_FEATURE_PREFIX}burst"));
+        }
+        tokens
+    }
+}
+
+fn length_bucket(length: usize) -> &'static str {
+    match length {
+        0..40 => "tiny",
+        40..200 => "short",
+        200..1000 => "medium",
+        1000..5000 => "long",
+        _ => "huge",
+    }
+}
+
+/// Bucket of the share of uppercase letters among cased letters
+fn caps_bucket(ratio: f64) -> &'static str {
+    match ratio {
+        0.0 => "none",
+        ratio if ratio <= 0.2 => "low",
+        ratio if ratio <= 0.5 => "mixed",
+        ratio if ratio < 1.0 => "high",
+        _ => "all",
+    }
+}
+
+/// Bucket of the share of digits among alphanumeric characters
+fn digits_bucket(ratio: f64) -> &'static str {
+    match ratio {
+        0.0 => "none",
+        ratio if ratio <= 0.1 => "low",
+        ratio if ratio <= 0.3 => "medium",
+        _ => "high",
+    }
+}
+
+fn exclamations_bucket(count: usize) -> &'static str {
+    match count {
+        0 => "none",
+        1 => "one",
+        2..=3 => "few",
+        _ => "many",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(input: &str) -> Vec<String> {
+        Style::measure(input).tokens()
+    }
+
+    #[test]
+    fn test_measure() {
+        let style = Style::measure("  FREE money!!! Win $1000 ");
+        assert_eq!(style.length, 23);
+        assert_eq!((style.cased_letters, style.uppercase_letters), (12, 5));
+        assert_eq!((style.alphanumeric, style.digits), (16, 4));
+        assert_eq!(style.exclamations, 3);
+        assert!(style.currency);
+        assert!(style.burst);
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            features("FREE MONEY!!! WIN $1000000 NOW!!!"),
+            [
+                "style:length:tiny",
+                "style:caps:all",
+                "style:digits:high",
+                "style:exclamations:many",
+                "style:currency",
+                "style:burst",
+            ]
+        );
+        assert_eq!(
+            features("Hi Bob, are we still meeting for lunch tomorrow at noon?"),
+            [
+                "style:length:short",
+                "style:caps:low",
+                "style:digits:none",
+                "style:exclamations:none",
+            ]
+        );
+
+        // Too few letters to tell the case
+        assert_eq!(
+            features("OK"),
+            [
+                "style:length:tiny",
+                "style:digits:none",
+                "style:exclamations:none"
+            ]
+        );
+        assert!(features(" \n").is_empty());
+    }
+}
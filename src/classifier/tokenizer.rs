@@ -0,0 +1,771 @@
+//! Text tokenization shared by the trainer and the classifier
+
+use std::borrow::Cow;
+
+use super::obfuscation::Normalized;
+use super::placeholders::{self, Replaced};
+use super::style::Style;
+use super::url::{self, Url};
+
+/// Key prefix of n-gram features, which can't clash with alphanumeric tokens
+pub const NGRAM_PREFIX: &str = "ngram:";
+
+/// Texts tokenized to fingerprint the tokenizer, exercising each of its stages
+const FINGERPRINT_PROBES: &[&str] = &[
+    "The runners were RUNNING faster than the jumping cats, and the children were laughing while walking home. Click here!!!",
+    "Gewinnen Sie jetzt, die Häuser warten auf Sie.",
+    "V1@gr@ fr\u{0435}\u{0435}\u{0435}, cl\u{200B}ick https://bit.ly/Offer",
+    "Win $1,000,000, call +1-800-555-0199 or write to win@prize.biz",
+];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Tokenization settings recorded in the model header
+///
+/// The classifier tokenizes inputs with the settings of its model, so that
+/// training and classification can't drift apart.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TokenizerConfig {
+    /// Whether words are lowercased
+    pub lowercase: bool,
+    /// Whether words are stemmed, in the language confidently detected for their sentence
+    pub stemming: bool,
+    /// Longest word n-gram emitted on top of unigrams, 1 for unigrams only
+    pub ngram_order: usize,
+    /// Whether URLs are kept as whole tokens along with their domain features
+    ///
+    /// Off for models trained before URL extraction, which don't record it.
+    #[serde(default)]
+    pub urls: bool,
+    /// Whether obfuscated words are normalized, emitting obfuscation features
+    ///
+    /// Off for models trained before normalization, which don't record it.
+    #[serde(default)]
+    pub deobfuscate: bool,
+    /// Whether stylometric features are emitted, such as the uppercase ratio
+    ///
+    /// Off for models trained before stylometric features, which don't record it.
+    #[serde(default)]
+    pub style: bool,
+    /// Whether money amounts, phone numbers, emails and numbers are replaced
+    /// with placeholder tokens such as `__money__`
+    ///
+    /// Off for models trained before placeholders, which don't record them.
+    #[serde(default)]
+    pub placeholders: bool,
+}
+
+/// Baseline settings, tokenizing inputs into lowercased and stemmed words only
+///
+/// These are the settings of models that don't record theirs, such as bare
+/// FST models and headers without tokenizer settings, which were trained
+/// before the other stages of the pipeline existed.
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            lowercase: true,
+            stemming: true,
+            ngram_order: 1,
+            urls: false,
+            deobfuscate: false,
+            style: false,
+            placeholders: false,
+        }
+    }
+}
+
+/// A normalized token along with its position in the original input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    /// Character offset of the token start
+    pub start: usize,
+    /// Character offset of the token end
+    pub end: usize,
+}
+
+impl TokenizerConfig {
+    /// Settings of newly trained models, with every stage of the pipeline
+    pub fn latest() -> Self {
+        Self {
+            urls: true,
+            deobfuscate: true,
+            style: true,
+            placeholders: true,
+            ..Default::default()
+        }
+    }
+
+    /// Fingerprint of the tokenization pipeline with these settings
+    ///
+    /// Models record the fingerprint of the tokenizer they were trained with,
+    /// so that a runtime tokenizing differently, for example after a change to
+    /// the segmentation chain, is caught when loading them.
+    ///
+    /// Only the behavior is hashed: the tokens, offsets and languages of
+    /// [`FINGERPRINT_PROBES`], which exercise every setting. A setting added
+    /// later, off in the headers that predate it, leaves their fingerprint as is.
+    pub fn fingerprint(&self) -> String {
+        let mut hash = FNV_OFFSET_BASIS;
+        for probe in FINGERPRINT_PROBES {
+            for token in self.tokenize_with_offsets(probe) {
+                hash = fnv1a(hash, token.text.as_bytes());
+                hash = fnv1a(hash, &(token.start as u64).to_le_bytes());
+                hash = fnv1a(hash, &(token.end as u64).to_le_bytes());
+            }
+            let language = self.detect_language(probe).unwrap_or_default();
+            hash = fnv1a(hash, language.as_bytes());
+            hash = fnv1a(hash, &[0]);
+        }
+
+        format!("{hash:016x}")
+    }
+
+    /// Tokenize the input into words and features
+    ///
+    /// Words and placeholders come first, then n-grams, then email domain
+    /// features, then URL tokens, then obfuscation and stylometric features.
+    pub fn tokenize(&self, input: &str) -> Vec<String> {
+        self.tokenize_with_offsets(input)
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    /// Tokenize the input, keeping track of where each token comes from
+    pub fn tokenize_with_offsets(&self, input: &str) -> Vec<Token> {
+        let (masked, urls) = self.extract_urls(input);
+        let replaced = self.replace_placeholders(&masked);
+        let normalized = self.normalize(&replaced.text);
+        let input = normalized
+            .as_ref()
+            .map_or(replaced.text.as_ref(), |n| n.text.as_str());
+        // Offsets in the normalized text are mapped back to the original input
+        let origin =
+            |index| replaced.origin(normalized.as_ref().map_or(index, |n| n.origin(index)));
+        let mut tokens = Vec::new();
+        let (mut byte_cursor, mut char_cursor) = (0, 0);
+
+        for token in self.segment(input) {
+            // Segments are slices of the input, appearing in order
+            let byte_start = input[byte_cursor..]
+                .find(token.text)
+                .map_or(byte_cursor, |offset| byte_cursor + offset);
+            let byte_end = byte_start + token.text.len();
+
+            let start = char_cursor + input[byte_cursor..byte_start].chars().count();
+            let end = start + token.text.chars().count();
+            (byte_cursor, char_cursor) = (byte_end, end);
+
+            tokens.push(Token {
+                text: token.get_text_prefer_normalized_owned(),
+                start: origin(start),
+                end: origin(end),
+            });
+        }
+
+        let words: Vec<_> = tokens.iter().map(|token| token.text.clone()).collect();
+        let ngrams: Vec<_> = self
+            .ngrams(&words)
+            .map(|((first, last), text)| Token {
+                text,
+                start: tokens[first].start,
+                end: tokens[last].end,
+            })
+            .collect();
+
+        tokens.extend(ngrams);
+        tokens.extend(replaced.placeholders.iter().filter_map(|placeholder| {
+            placeholder.feature().map(|text| Token {
+                text,
+                start: placeholder.start,
+                end: placeholder.end,
+            })
+        }));
+        tokens.extend(urls.iter().flat_map(|url| {
+            url.tokens().into_iter().map(|text| Token {
+                text,
+                start: url.start,
+                end: url.end,
+            })
+        }));
+        tokens.extend(
+            normalized
+                .iter()
+                .flat_map(|n| &n.findings)
+                .map(|finding| Token {
+                    text: finding.kind.token(),
+                    start: replaced.origin(finding.start),
+                    end: replaced.origin(finding.end),
+                }),
+        );
+
+        // Stylometric features span the whole input
+        let length = masked.chars().count();
+        tokens.extend(self.style(&masked).into_iter().map(|text| Token {
+            text,
+            start: 0,
+            end: length,
+        }));
+        tokens
+    }
+
+    /// Stylometric features of the input, without its URLs, when enabled
+    fn style(&self, input: &str) -> Vec<String> {
+        if self.style {
+            Style::measure(input).tokens()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Input with its obfuscation undone, when enabled
+    fn normalize(&self, input: &str) -> Option<Normalized> {
+        self.deobfuscate.then(|| Normalized::new(input))
+    }
+
+    /// URLs of the input when enabled, along with the input they are blanked out of
+    fn extract_urls<'a>(&self, input: &'a str) -> (Cow<'a, str>, Vec<Url>) {
+        let urls = if self.urls {
+            url::extract(input)
+        } else {
+            Vec::new()
+        };
+
+        if urls.is_empty() {
+            (Cow::Borrowed(input), urls)
+        } else {
+            let spans = urls.iter().map(|url| (url.start, url.end));
+            (Cow::Owned(mask(input, spans)), urls)
+        }
+    }
+
+    /// Input with its values replaced with placeholder tokens, when enabled
+    fn replace_placeholders<'a>(&self, input: &'a str) -> Replaced<'a> {
+        if self.placeholders {
+            placeholders::replace(input)
+        } else {
+            Replaced::unchanged(input)
+        }
+    }
+
+    /// Detect the dominant language of the input
+    ///
+    /// Returns the ISO 639-3 code of the language most tokens were detected in,
+    /// or `None` when no language could be detected.
+    pub fn detect_language(&self, input: &str) -> Option<&'static str> {
+        let mut languages: Vec<(&'static str, usize)> = Vec::new();
+        let normalized = self.normalize(input);
+        let input = normalized.as_ref().map_or(input, |n| n.text.as_str());
+
+        for language in self
+            .segment(input)
+            .filter_map(|token| token.detected_language)
+        {
+            let code = language.code();
+            match languages.iter_mut().find(|(known, _)| *known == code) {
+                Some((_, count)) => *count += 1,
+                None => languages.push((code, 1)),
+            }
+        }
+
+        // Ties go to the language seen first
+        languages
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .map(|(code, _)| code)
+    }
+
+    /// Split the input into alphanumeric words, lowercased and stemmed when enabled
+    ///
+    /// Lowercasing runs first, and stemming leaves the words it changed as is.
+    fn segment<'a>(
+        &self,
+        input: &'a str,
+    ) -> impl Iterator<Item = unobtanium_segmenter::SegmentedToken<'a>> {
+        use unobtanium_segmenter::augmentation::{
+            AugmentationClassify, AugmentationDetectLanguage, Augmenter,
+        };
+        use unobtanium_segmenter::chain::{ChainAugmenter, ChainSegmenter, StartSegmentationChain};
+        use unobtanium_segmenter::normalization::{
+            NormalizationLowercase, NormalizationRustStemmers,
+        };
+        use unobtanium_segmenter::segmentation::{UnicodeSentenceSplitter, UnicodeWordSplitter};
+        use unobtanium_segmenter::SegmentedTokenKind;
+
+        let lowercase = self.lowercase.then(NormalizationLowercase::new);
+        let stemmer = self.stemming.then(NormalizationRustStemmers::new);
+
+        input
+            .start_segmentation_chain()
+            .chain_owned_segmenter(UnicodeSentenceSplitter::new())
+            .chain_owned_augmenter(AugmentationDetectLanguage::new())
+            .chain_owned_segmenter(UnicodeWordSplitter::new())
+            .chain_owned_augmenter(AugmentationClassify::new())
+            .map(move |token| match &lowercase {
+                Some(lowercase) => lowercase.augment(token),
+                None => token,
+            })
+            .map(move |token| match &stemmer {
+                Some(stemmer) => stemmer.augment(token),
+                None => token,
+            })
+            .filter(|token| token.kind == Some(SegmentedTokenKind::AlphaNumeric))
+    }
+
+    /// Build the n-grams of order 2 and up, along with the indices of their first and last words
+    fn ngrams<'a>(
+        &self,
+        words: &'a [String],
+    ) -> impl Iterator<Item = ((usize, usize), String)> + 'a {
+        (2..=self.ngram_order).flat_map(move |order| {
+            words
+                .windows(order)
+                .enumerate()
+                .map(move |(first, window)| {
+                    let ngram = format!("{NGRAM_PREFIX}{}", window.join(" "));
+                    ((first, first + order - 1), ngram)
+                })
+        })
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across platforms and compiler versions unlike the std hashers
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Blank out character spans of an input so that word segmentation skips them
+///
+/// Spans must be sorted and not overlap. Characters are replaced one for one,
+/// keeping character offsets.
+fn mask(input: &str, spans: impl IntoIterator<Item = (usize, usize)>) -> String {
+    let mut spans = spans.into_iter().peekable();
+    input
+        .chars()
+        .enumerate()
+        .map(|(index, c)| {
+            while spans.next_if(|&(_, end)| end <= index).is_some() {}
+            match spans.peek() {
+                Some(&(start, _)) if start <= index => ' ',
+                _ => c,
+            }
+        })
+        .collect()
+}
+
+/// Inputs the classifier can tokenize
+///
+/// Plain texts are tokenized as is, structured inputs may prefix the tokens of
+/// each of their fields.
+pub trait Document {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String>;
+
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token>;
+
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str>;
+
+    /// URLs of the input, in order
+    fn urls(&self) -> Vec<Url>;
+
+    /// Text a reader would see, as matched by operator rules
+    fn visible_text(&self) -> Cow<'_, str>;
+}
+
+impl Document for str {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String> {
+        tokenizer.tokenize(self)
+    }
+
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token> {
+        tokenizer.tokenize_with_offsets(self)
+    }
+
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str> {
+        tokenizer.detect_language(self)
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        url::extract(self)
+    }
+
+    fn visible_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Document for String {
+    fn tokenize(&self, tokenizer: &TokenizerConfig) -> Vec<String> {
+        self.as_str().tokenize(tokenizer)
+    }
+
+    fn tokenize_with_offsets(&self, tokenizer: &TokenizerConfig) -> Vec<Token> {
+        self.as_str().tokenize_with_offsets(tokenizer)
+    }
+
+    fn detect_language(&self, tokenizer: &TokenizerConfig) -> Option<&'static str> {
+        self.as_str().detect_language(tokenizer)
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        self.as_str().urls()
+    }
+
+    fn visible_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// Tokenize the input with the default settings
+#[allow(dead_code)]
+pub fn tokenize(input: &str) -> Vec<String> {
+    TokenizerConfig::default().tokenize(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Latest settings without stylometric features, which every input gets
+    fn config(ngram_order: usize) -> TokenizerConfig {
+        TokenizerConfig {
+            ngram_order,
+            style: false,
+            ..TokenizerConfig::latest()
+        }
+    }
+
+    #[test]
+    fn test_tokenization_offsets() {
+        let text = "Héllo wörld! FREE money";
+        let tokens = config(1).tokenize_with_offsets(text);
+
+        assert_eq!(
+            tokens.iter().map(|token| &token.text).collect::<Vec<_>>(),
+            config(1).tokenize(text).iter().collect::<Vec<_>>()
+        );
+
+        let chars: Vec<char> = text.chars().collect();
+        for token in &tokens {
+            let original: String = chars[token.start..token.end].iter().collect();
+            assert!(
+                original.to_lowercase().starts_with(&token.text[..1]),
+                "Token {:?} doesn't match original text {:?}",
+                token,
+                original
+            );
+        }
+
+        assert_eq!((tokens[1].start, tokens[1].end), (6, 11));
+    }
+
+    #[test]
+    fn test_lowercase_and_stemming() {
+        let text = "The runners were RUNNING faster than the jumping cats, \
+                    and the children were laughing while walking home";
+        let words = |tokenizer: TokenizerConfig| tokenizer.tokenize(text)[1..8].to_vec();
+
+        // Lowercased words aren't stemmed
+        assert_eq!(
+            words(config(1)),
+            ["runner", "were", "running", "faster", "than", "the", "jump"]
+        );
+        assert_eq!(
+            words(TokenizerConfig {
+                stemming: false,
+                ..config(1)
+            }),
+            ["runners", "were", "running", "faster", "than", "the", "jumping"]
+        );
+        assert_eq!(
+            words(TokenizerConfig {
+                lowercase: false,
+                ..config(1)
+            }),
+            ["runner", "were", "RUNNING", "faster", "than", "the", "jump"]
+        );
+    }
+
+    #[test]
+    fn test_ngrams() {
+        let trigrams = config(3);
+
+        let unigrams = config(1).tokenize("click here now");
+        let tokens = trigrams.tokenize("click here now");
+
+        assert_eq!(&tokens[..unigrams.len()], unigrams.as_slice());
+        assert_eq!(
+            &tokens[unigrams.len()..],
+            &[
+                format!("{NGRAM_PREFIX}click here"),
+                format!("{NGRAM_PREFIX}here now"),
+                format!("{NGRAM_PREFIX}click here now"),
+            ]
+        );
+
+        // Unigram-only configurations emit no n-grams
+        assert_eq!(config(1).tokenize("click here now"), unigrams);
+
+        // Inputs shorter than the order only produce the n-grams that fit
+        assert_eq!(trigrams.tokenize("act now").len(), 3);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let tokenizer = config(1);
+
+        assert_eq!(
+            tokenizer.detect_language("Hello, I would like to book a table for two people tonight"),
+            Some("eng")
+        );
+        assert_eq!(
+            tokenizer.detect_language(
+                "Guten Tag, ich möchte heute Abend einen Tisch für zwei Personen reservieren"
+            ),
+            Some("deu")
+        );
+        assert_eq!(tokenizer.detect_language(""), None);
+    }
+
+    #[test]
+    fn test_urls() {
+        let text = "Claim at https://bit.ly/Prize now";
+        let tokens = config(2).tokenize_with_offsets(text);
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        // URLs aren't split into words, and don't break n-grams apart
+        assert_eq!(
+            texts,
+            [
+                "claim",
+                "at",
+                "now",
+                "ngram:claim at",
+                "ngram:at now",
+                "url:bit.ly/prize",
+                "domain:bit.ly",
+                "regdomain:bit.ly",
+                "tld:ly",
+                url::SHORTENER_FEATURE,
+            ]
+        );
+        assert_eq!((tokens[5].start, tokens[5].end), (9, 29));
+
+        let without_urls = TokenizerConfig {
+            urls: false,
+            ..config(1)
+        };
+        // Without them, the host is a dotted word among those of the URL
+        assert_eq!(
+            without_urls.tokenize(text),
+            ["claim", "at", "https", "bit.ly", "prize", "now"]
+        );
+    }
+
+    #[test]
+    fn test_deobfuscate() {
+        let text = "Fr\u{0435}\u{0435} V1@gr@, cl\u{200B}ick";
+        let tokens = config(1).tokenize_with_offsets(text);
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        assert_eq!(
+            texts,
+            [
+                "free",
+                "viagra",
+                "click",
+                "obfuscation:invisible",
+                "obfuscation:confusable",
+                "obfuscation:leetspeak",
+            ]
+        );
+        // Offsets point to the original words
+        assert_eq!((tokens[1].start, tokens[1].end), (5, 11));
+        assert_eq!((tokens[2].start, tokens[2].end), (13, 19));
+
+        let without_deobfuscation = TokenizerConfig {
+            deobfuscate: false,
+            ..config(1)
+        };
+        assert!(without_deobfuscation
+            .tokenize(text)
+            .contains(&"v1".to_string()));
+    }
+
+    #[test]
+    fn test_style() {
+        let text = "FREE MONEY at https://example.com/OFFER!!!";
+        let tokenizer = TokenizerConfig::latest();
+        let tokens = tokenizer.tokenize_with_offsets(text);
+
+        // URLs count towards the length of the input, but not its case or digits
+        let style: Vec<_> = tokens
+            .iter()
+            .filter(|token| {
+                token
+                    .text
+                    .starts_with(super::super::style::STYLE_FEATURE_PREFIX)
+            })
+            .collect();
+        assert_eq!(
+            style
+                .iter()
+                .map(|token| token.text.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "style:length:short",
+                "style:caps:high",
+                "style:digits:none",
+                "style:exclamations:few",
+                "style:burst",
+            ]
+        );
+        assert_eq!((style[0].start, style[0].end), (0, text.chars().count()));
+
+        assert_eq!(
+            tokens.iter().map(|token| &token.text).collect::<Vec<_>>(),
+            tokenizer.tokenize(text).iter().collect::<Vec<_>>()
+        );
+        assert!(config(1)
+            .tokenize(text)
+            .iter()
+            .all(|token| !token.starts_with("style:")));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let text = "Send $500 to win@prize.biz or call 555-0199";
+        let tokens = config(2).tokenize_with_offsets(text);
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        assert_eq!(
+            texts,
+            [
+                "send",
+                "__money__",
+                "to",
+                "__email__",
+                "or",
+                "call",
+                "__phone__",
+                "ngram:send __money__",
+                "ngram:__money__ to",
+                "ngram:to __email__",
+                "ngram:__email__ or",
+                "ngram:or call",
+                "ngram:call __phone__",
+                "emaildomain:prize.biz",
+            ]
+        );
+        // Placeholders and their n-grams point to the values they replace
+        assert_eq!((tokens[1].start, tokens[1].end), (5, 9));
+        assert_eq!((tokens[3].start, tokens[3].end), (13, 26));
+        assert_eq!((tokens[8].start, tokens[8].end), (5, 12));
+        assert_eq!((tokens[13].start, tokens[13].end), (13, 26));
+        assert_eq!(texts, config(2).tokenize(text));
+
+        // Email addresses are replaced whole, their domain is a prefixed feature
+        let tokens = config(1).tokenize("Write to win@prize.biz or visit https://prize.biz/claim");
+        assert_eq!(
+            tokens,
+            [
+                "write",
+                "to",
+                "__email__",
+                "or",
+                "visit",
+                "emaildomain:prize.biz",
+                "url:prize.biz/claim",
+                "domain:prize.biz",
+                "regdomain:prize.biz",
+                "tld:biz",
+            ]
+        );
+
+        let without_placeholders = TokenizerConfig {
+            placeholders: false,
+            ..config(1)
+        };
+        assert!(without_placeholders
+            .tokenize(text)
+            .contains(&"win".to_string()));
+    }
+
+    #[test]
+    fn test_mask() {
+        let text = "Go to https://é.example.com now";
+        let masked = mask(text, [(6, 27)]);
+        assert_eq!(masked, format!("Go to {} now", " ".repeat(21)));
+        assert_eq!(masked.chars().count(), text.chars().count());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fingerprint = config(1).fingerprint();
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, config(1).fingerprint());
+
+        // Every setting changes how the probes are tokenized
+        let baseline = TokenizerConfig::default();
+        let latest = TokenizerConfig::latest();
+        for changed in [
+            TokenizerConfig {
+                lowercase: false,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                stemming: false,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                ngram_order: 2,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                urls: false,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                deobfuscate: false,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                style: false,
+                ..latest.clone()
+            },
+            TokenizerConfig {
+                placeholders: false,
+                ..latest.clone()
+            },
+            baseline.clone(),
+        ] {
+            assert_ne!(changed.fingerprint(), latest.fingerprint(), "{changed:?}");
+        }
+
+        // Models without a fingerprint are checked against the baseline one
+        assert_eq!(
+            baseline.fingerprint(),
+            super::super::model::BASELINE_TOKENIZER_FINGERPRINT
+        );
+    }
+
+    #[test]
+    fn test_ngram_offsets() {
+        let bigrams = config(2);
+
+        let tokens = bigrams.tokenize_with_offsets("Act now, click here");
+        let texts: Vec<_> = tokens.iter().map(|token| token.text.clone()).collect();
+        assert_eq!(texts, bigrams.tokenize("Act now, click here"));
+
+        let bigram = tokens
+            .iter()
+            .find(|token| token.text == format!("{NGRAM_PREFIX}click here"))
+            .unwrap();
+        assert_eq!((bigram.start, bigram.end), (9, 19));
+    }
+}
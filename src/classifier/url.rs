@@ -0,0 +1,302 @@
+//! URL extraction and domain-level features
+//!
+//! Word segmentation breaks URLs into meaningless fragments, so they are
+//! found beforehand and kept as whole tokens. Each URL also contributes
+//! features for its domain, registered domain and TLD, and flags for link
+//! shorteners and IP literal hosts.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Key prefix of whole URLs, without their scheme
+pub const URL_PREFIX: &str = "url:";
+/// Key prefix of URL hosts, without their `www.` label
+pub const DOMAIN_PREFIX: &str = "domain:";
+/// Key prefix of registered domains, such as `example.co.uk` for `mail.example.co.uk`
+pub const REGISTERED_DOMAIN_PREFIX: &str = "regdomain:";
+pub const TLD_PREFIX: &str = "tld:";
+
+/// Feature of URLs pointing to a link shortener
+pub const SHORTENER_FEATURE: &str = "link:shortener";
+/// Feature of URLs with an IP address as host
+pub const IP_HOST_FEATURE: &str = "link:ip_host";
+
+/// Registered domains of well-known link shorteners
+const SHORTENERS: &[&str] = &[
+    "bit.ly",
+    "bl.ink",
+    "buff.ly",
+    "cutt.ly",
+    "goo.gl",
+    "is.gd",
+    "lnkd.in",
+    "ow.ly",
+    "rb.gy",
+    "rebrand.ly",
+    "s.id",
+    "shorturl.at",
+    "t.co",
+    "t.ly",
+    "tiny.cc",
+    "tinyurl.com",
+    "v.gd",
+];
+
+/// Second-level labels under which country code TLDs register domains, as in `co.uk`
+///
+/// A short approximation of the public suffix list, which is too large to embed.
+const SECOND_LEVEL_LABELS: &[&str] = &[
+    "ac", "co", "com", "edu", "go", "gov", "ne", "net", "or", "org",
+];
+
+/// Characters ending a URL in free text
+const URL_TERMINATORS: &[char] = &['<', '>', '"', '\'', '`', '{', '}', '|', '\\', '^'];
+
+/// Punctuation trimmed from the end of URLs, as in "see example.com/offer."
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '*'];
+
+/// A URL found in an input
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Url {
+    /// URL as found in the input
+    pub url: String,
+    /// Lowercased host, without its `www.` label
+    pub domain: String,
+    pub registered_domain: String,
+    /// Top-level domain, `None` for IP literal hosts
+    pub tld: Option<String>,
+    pub shortener: bool,
+    pub ip: bool,
+    /// Character offset of the URL start in the input
+    #[serde(skip)]
+    pub start: usize,
+    /// Character offset of the URL end in the input
+    #[serde(skip)]
+    pub end: usize,
+}
+
+impl Url {
+    /// Parse an absolute `http(s)://` URL, or one starting with `www.`
+    pub fn parse(url: &str) -> Option<Self> {
+        let url = url.trim();
+        let lowercase = url.to_ascii_lowercase();
+        let rest = match ["http://", "https://"]
+            .iter()
+            .find_map(|scheme| lowercase.strip_prefix(scheme))
+        {
+            Some(rest) => rest,
+            None if lowercase.starts_with("www.") => &lowercase,
+            None => return None,
+        };
+
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        // User info is a classic trick, as in `http://paypal.com@example.com`
+        let host = authority.rsplit('@').next().unwrap_or_default();
+
+        if let Some(literal) = host.strip_prefix('[') {
+            let address = literal.split(']').next().unwrap_or_default();
+            address.parse::<Ipv6Addr>().ok()?;
+            return Some(Self::ip_literal(url, &format!("[{address}]")));
+        }
+
+        let host = host.split(':').next().unwrap_or_default();
+        let host = host.trim_end_matches('.');
+        if host.parse::<Ipv4Addr>().is_ok() {
+            return Some(Self::ip_literal(url, host));
+        }
+
+        let valid = host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+        let tld = host.rsplit('.').next().unwrap_or_default();
+        let valid_tld = tld.chars().count() >= 2
+            && (tld.chars().all(char::is_alphabetic) || tld.starts_with("xn--"));
+        if !valid || !host.contains('.') || !valid_tld {
+            return None;
+        }
+
+        let domain = host.strip_prefix("www.").unwrap_or(host);
+        let registered_domain = registered_domain(domain);
+
+        Some(Self {
+            url: url.to_string(),
+            domain: domain.to_string(),
+            shortener: SHORTENERS.contains(&registered_domain.as_str()),
+            registered_domain,
+            tld: Some(tld.to_string()),
+            ip: false,
+            start: 0,
+            end: 0,
+        })
+    }
+
+    fn ip_literal(url: &str, host: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            domain: host.to_string(),
+            registered_domain: host.to_string(),
+            tld: None,
+            shortener: false,
+            ip: true,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// The URL as a whole token and its domain-level features
+    pub fn tokens(&self) -> Vec<String> {
+        let lowercase = self.url.to_lowercase();
+        let normalized = ["http://", "https://"]
+            .iter()
+            .find_map(|scheme| lowercase.strip_prefix(scheme))
+            .unwrap_or(&lowercase);
+        let normalized = normalized.strip_prefix("www.").unwrap_or(normalized);
+
+        let mut tokens = vec![
+            format!("{URL_PREFIX}{}", normalized.trim_end_matches('/')),
+            format!("{DOMAIN_PREFIX}{}", self.domain),
+            format!("{REGISTERED_DOMAIN_PREFIX}{}", self.registered_domain),
+        ];
+        if let Some(tld) = &self.tld {
+            tokens.push(format!("{TLD_PREFIX}{tld}"));
+        }
+        if self.shortener {
+            tokens.push(SHORTENER_FEATURE.to_string());
+        }
+        if self.ip {
+            tokens.push(IP_HOST_FEATURE.to_string());
+        }
+        tokens
+    }
+}
+
+/// Domain under which the host was registered
+fn registered_domain(domain: &str) -> String {
+    let labels: Vec<_> = domain.split('.').collect();
+    let count = match labels.as_slice() {
+        [.., second, tld]
+            if labels.len() >= 3 && tld.len() == 2 && SECOND_LEVEL_LABELS.contains(second) =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(count)..].join(".")
+}
+
+/// Find the URLs of a text, in order
+pub fn extract(input: &str) -> Vec<Url> {
+    // ASCII lowercasing keeps byte offsets
+    let lowercase = input.to_ascii_lowercase();
+    let mut urls = Vec::new();
+    let (mut cursor, mut char_cursor) = (0, 0);
+
+    while let Some(start) = find_start(&lowercase, cursor) {
+        let length = input[start..]
+            .find(|c: char| c.is_whitespace() || URL_TERMINATORS.contains(&c))
+            .unwrap_or(input.len() - start);
+        let raw = input[start..start + length].trim_end_matches(TRAILING_PUNCTUATION);
+
+        if let Some(mut url) = Url::parse(raw) {
+            url.start = char_cursor + input[cursor..start].chars().count();
+            url.end = url.start + raw.chars().count();
+            (cursor, char_cursor) = (start + raw.len(), url.end);
+            urls.push(url);
+        } else {
+            let skipped = start + 1;
+            char_cursor += input[cursor..skipped].chars().count();
+            cursor = skipped;
+        }
+    }
+
+    urls
+}
+
+/// Byte offset of the next URL start, at a word boundary
+fn find_start(lowercase: &str, from: usize) -> Option<usize> {
+    let mut cursor = from;
+    loop {
+        let rest = &lowercase[cursor..];
+        let start = ["http://", "https://", "www."]
+            .iter()
+            .filter_map(|prefix| rest.find(prefix))
+            .min()?
+            + cursor;
+
+        let boundary = lowercase[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !(c.is_alphanumeric() || matches!(c, '.' | '/' | '@' | '-')));
+        if boundary {
+            return Some(start);
+        }
+        cursor = start + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(text: &str) -> Vec<String> {
+        extract(text).into_iter().map(|url| url.url).collect()
+    }
+
+    #[test]
+    fn test_extract() {
+        let text = "Vérifiez http://WWW.Example.co.uk/Offer?id=1. Or (see www.bit.ly/x), not notwww.foo.com";
+        let urls = extract(text);
+
+        assert_eq!(
+            found(text),
+            ["http://WWW.Example.co.uk/Offer?id=1", "www.bit.ly/x"]
+        );
+        assert_eq!(
+            found("<a>https://a.example.org/</a>"),
+            ["https://a.example.org/"]
+        );
+
+        let chars: Vec<char> = text.chars().collect();
+        for url in &urls {
+            let original: String = chars[url.start..url.end].iter().collect();
+            assert_eq!(original, url.url);
+        }
+
+        assert_eq!(urls[0].domain, "example.co.uk");
+        assert_eq!(urls[0].registered_domain, "example.co.uk");
+        assert_eq!(urls[0].tld.as_deref(), Some("uk"));
+        assert!(!urls[0].shortener);
+        assert!(urls[1].shortener);
+    }
+
+    #[test]
+    fn test_hosts() {
+        let url = Url::parse("https://paypal.com@login.secure.example.com:8080/").unwrap();
+        assert_eq!(url.domain, "login.secure.example.com");
+        assert_eq!(url.registered_domain, "example.com");
+
+        let url = Url::parse("http://192.168.0.1/admin").unwrap();
+        assert!(url.ip);
+        assert_eq!(url.tld, None);
+        assert!(url.tokens().contains(&IP_HOST_FEATURE.to_string()));
+        assert!(Url::parse("http://[::1]:80/").unwrap().ip);
+
+        assert_eq!(Url::parse("http://localhost/"), None);
+        assert_eq!(Url::parse("http://example.123/"), None);
+        assert_eq!(Url::parse("/relative/path"), None);
+    }
+
+    #[test]
+    fn test_tokens() {
+        let url = Url::parse("https://www.tinyurl.com/Abc/").unwrap();
+        assert_eq!(
+            url.tokens(),
+            [
+                format!("{URL_PREFIX}tinyurl.com/abc"),
+                format!("{DOMAIN_PREFIX}tinyurl.com"),
+                format!("{REGISTERED_DOMAIN_PREFIX}tinyurl.com"),
+                format!("{TLD_PREFIX}com"),
+                SHORTENER_FEATURE.to_string(),
+            ]
+        );
+    }
+}
@@ -1,6 +1,17 @@
+use std::marker::PhantomData;
+
 use crate::bindings::wasi::http::types::IncomingBody;
 use anyhow::Result;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+
+use super::extensions::BodyReader;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+pub const RFC822_CONTENT_TYPE: &str = "message/rfc822";
+pub const HTML_CONTENT_TYPE: &str = "text/html";
+
+/// Longest NDJSON line read, in bytes, so that a missing newline can't exhaust memory
+pub const MAX_NDJSON_LINE_LENGTH: usize = 4 * 1024 * 1024;
 
 pub trait FromBody: Sized {
     fn from_data(data: Bytes) -> Result<Self>;
@@ -17,6 +28,14 @@ pub trait IntoBody: Sized {
     fn extend_response_parts(&self, parts: &mut http::response::Parts) {}
 }
 
+/// Response bodies written incrementally instead of being buffered
+pub trait IntoBodyStream: Sized {
+    fn write_body(self, write: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()>;
+
+    #[allow(unused_variables)]
+    fn extend_response_parts(&self, parts: &mut http::response::Parts) {}
+}
+
 impl FromBody for IncomingBody {
     fn from_data(_: Bytes) -> Result<Self> {
         unimplemented!("Should never be called")
@@ -121,6 +140,19 @@ impl<T: serde::Serialize> IntoBody for Json<T> {
     }
 }
 
+/// Raw RFC 5322 / MIME message body, parsed with `T::try_from`
+#[derive(Debug, Clone)]
+pub struct Rfc822<T>(pub T);
+
+impl<T> FromBody for Rfc822<T>
+where
+    T: for<'a> TryFrom<&'a [u8], Error = anyhow::Error>,
+{
+    fn from_data(data: Bytes) -> Result<Self> {
+        Ok(Self(T::try_from(&data)?))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RawJson<T>(pub T);
 
@@ -137,6 +169,12 @@ impl<T: Into<Bytes>> IntoBody for RawJson<T> {
 #[derive(Debug, Clone)]
 pub struct Html<T>(pub T);
 
+impl<T: FromBody> FromBody for Html<T> {
+    fn from_data(data: Bytes) -> Result<Self> {
+        T::from_data(data).map(Self)
+    }
+}
+
 impl<T: Into<Bytes>> IntoBody for Html<T> {
     fn into_body(self) -> Result<Bytes> {
         Ok(self.0.into())
@@ -149,3 +187,137 @@ impl<T: Into<Bytes>> IntoBody for Html<T> {
             .or_insert(http::HeaderValue::from_static("text/html; charset=utf-8"));
     }
 }
+
+/// Newline-delimited JSON body, read and parsed one line at a time
+///
+/// Only the line being read is kept in memory. Blank lines are skipped, and
+/// lines that fail to parse are yielded as errors without ending the iteration.
+/// Lines longer than [`MAX_NDJSON_LINE_LENGTH`] are skipped as they are read,
+/// and yielded as errors too.
+pub struct NdJson<T> {
+    reader: Option<BodyReader>,
+    buffer: BytesMut,
+    /// Length of the buffer start known not to contain a newline
+    scanned: usize,
+    /// Whether the line being read is too long, and its bytes dropped until the next newline
+    discarding: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T> NdJson<T> {
+    fn next_line(&mut self) -> Option<Result<Bytes>> {
+        loop {
+            if let Some(position) = self.buffer[self.scanned..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+            {
+                let end = self.scanned + position;
+                let mut line = self.buffer.split_to(end + 1);
+                line.truncate(end);
+                self.scanned = 0;
+                if std::mem::take(&mut self.discarding) || line.len() > MAX_NDJSON_LINE_LENGTH {
+                    return Some(Err(line_too_long()));
+                }
+                return Some(Ok(line.freeze()));
+            }
+
+            if self.discarding || self.buffer.len() > MAX_NDJSON_LINE_LENGTH {
+                self.discarding = true;
+                self.buffer.clear();
+            }
+            self.scanned = self.buffer.len();
+
+            let chunk = match self.reader.as_ref().map(BodyReader::read_chunk) {
+                Some(Ok(Some(chunk))) => chunk,
+                Some(Err(err)) => {
+                    self.reader = None;
+                    self.buffer.clear();
+                    self.scanned = 0;
+                    return Some(Err(err));
+                }
+                Some(Ok(None)) | None => {
+                    // The last line may not end with a newline
+                    self.reader = None;
+                    self.scanned = 0;
+                    if std::mem::take(&mut self.discarding) {
+                        return Some(Err(line_too_long()));
+                    }
+                    return (!self.buffer.is_empty()).then(|| Ok(self.buffer.split().freeze()));
+                }
+            };
+            self.buffer.extend_from_slice(&chunk);
+        }
+    }
+}
+
+fn line_too_long() -> anyhow::Error {
+    anyhow::anyhow!("Line longer than {MAX_NDJSON_LINE_LENGTH} bytes")
+}
+
+impl<T: serde::de::DeserializeOwned> Iterator for NdJson<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.next_line()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            return Some(serde_json::from_slice(&line).map_err(Into::into));
+        }
+    }
+}
+
+impl<T: serde::de::DeserializeOwned> FromBody for NdJson<T> {
+    fn from_data(data: Bytes) -> Result<Self> {
+        Ok(Self {
+            reader: None,
+            buffer: BytesMut::from(&data[..]),
+            scanned: 0,
+            discarding: false,
+            _marker: PhantomData,
+        })
+    }
+
+    fn from_body(body: IncomingBody) -> Result<Self> {
+        Ok(Self {
+            reader: Some(body.into_reader()?),
+            buffer: BytesMut::new(),
+            scanned: 0,
+            discarding: false,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Newline-delimited JSON response, serializing and writing one item at a time
+#[derive(Debug, Clone)]
+pub struct NdJsonStream<T>(pub T);
+
+impl<T> IntoBodyStream for NdJsonStream<T>
+where
+    T: IntoIterator,
+    T::Item: serde::Serialize,
+{
+    fn write_body(self, write: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let mut line = Vec::with_capacity(128);
+        for item in self.0 {
+            line.clear();
+            serde_json::to_writer(&mut line, &item)?;
+            line.push(b'\n');
+            write(&line)?;
+        }
+        Ok(())
+    }
+
+    fn extend_response_parts(&self, parts: &mut http::response::Parts) {
+        parts
+            .headers
+            .entry(http::header::CONTENT_TYPE)
+            .or_insert(http::HeaderValue::from_static(NDJSON_CONTENT_TYPE));
+    }
+}
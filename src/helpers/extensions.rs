@@ -2,9 +2,13 @@ use bytes::Bytes;
 use serde::de::DeserializeOwned;
 
 use crate::bindings::wasi::http::types::{
-    ErrorCode, Headers, IncomingBody, IncomingRequest, Method, ResponseOutparam,
+    ErrorCode, Headers, IncomingBody, IncomingRequest, InputStream, Method, OutgoingBody,
+    OutputStream, ResponseOutparam,
 };
 
+/// Maximum number of bytes read from or written to a stream at once
+const CHUNK_SIZE: usize = 4096;
+
 impl TryFrom<Method> for http::Method {
     type Error = anyhow::Error;
 
@@ -131,6 +135,64 @@ impl IncomingBody {
         let bytes = self.read()?;
         Ok(serde_json::from_slice(&bytes)?)
     }
+
+    /// Get a reader of the body, for reading it chunk by chunk instead of all at once
+    pub fn into_reader(self) -> anyhow::Result<BodyReader> {
+        let stream = self
+            .stream()
+            .map_err(|_| anyhow::anyhow!("Missing request body stream"))?;
+
+        Ok(BodyReader {
+            stream,
+            _body: self,
+        })
+    }
+}
+
+/// Incremental reader of a request body
+pub struct BodyReader {
+    // The stream is a child resource of the body, and must be dropped first
+    stream: InputStream,
+    _body: IncomingBody,
+}
+
+impl BodyReader {
+    /// Wait for the next chunk of the body, `None` once the body is over
+    pub fn read_chunk(&self) -> anyhow::Result<Option<Bytes>> {
+        use crate::bindings::wasi::io::streams::StreamError;
+
+        match self.stream.blocking_read(CHUNK_SIZE as u64) {
+            Ok(chunk) => Ok(Some(chunk.into())),
+            Err(StreamError::Closed) => Ok(None),
+            Err(err) => anyhow::bail!("Failed reading request body: {err}"),
+        }
+    }
+}
+
+/// Incremental writer of a response body
+pub struct BodyWriter {
+    // The stream is a child resource of the body, and must be dropped first
+    stream: OutputStream,
+    body: OutgoingBody,
+}
+
+impl BodyWriter {
+    /// Write and flush data, in chunks no larger than the stream accepts at once
+    pub fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            self.stream.blocking_write_and_flush(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Complete the response body
+    pub fn finish(self) -> anyhow::Result<()> {
+        let Self { stream, body } = self;
+        drop(stream);
+
+        OutgoingBody::finish(body, None)?;
+        Ok(())
+    }
 }
 
 impl ResponseOutparam {
@@ -139,27 +201,30 @@ impl ResponseOutparam {
     }
 
     pub fn send(self, res: http::Response<Bytes>) -> anyhow::Result<()> {
-        use crate::bindings::wasi::http::types::{OutgoingBody, OutgoingResponse};
-
         let (parts, body) = res.into_parts();
 
+        let writer = self.start(parts)?;
+        writer.write(&body)?;
+        writer.finish()
+    }
+
+    /// Send the response status and headers, returning a writer for its body
+    pub fn start(self, parts: http::response::Parts) -> anyhow::Result<BodyWriter> {
+        use crate::bindings::wasi::http::types::OutgoingResponse;
+
         let res = OutgoingResponse::new(parts.headers.into());
         let _ = res.set_status_code(parts.status.into());
 
-        let resp_body = res
+        let body = res
             .body()
             .map_err(|_| anyhow::anyhow!("Could not get response body"))?;
 
         ResponseOutparam::set(self, Ok(res));
 
-        let out = resp_body
+        let stream = body
             .write()
             .map_err(|_| anyhow::anyhow!("Could not get response body writer"))?;
-        out.blocking_write_and_flush(&body)?;
-        drop(out);
 
-        OutgoingBody::finish(resp_body, None)?;
-
-        Ok(())
+        Ok(BodyWriter { stream, body })
     }
 }
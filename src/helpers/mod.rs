@@ -3,8 +3,8 @@ use anyhow::Result;
 use bytes::Bytes;
 use http::{Request, Response, StatusCode};
 
-use crate::bindings::wasi::http::types::{IncomingRequest, ResponseOutparam};
-use body::{FromBody, IntoBody, Json};
+use crate::bindings::wasi::http::types::{IncomingBody, IncomingRequest, ResponseOutparam};
+use body::{FromBody, IntoBody, IntoBodyStream, Json};
 
 pub mod body;
 mod extensions;
@@ -17,20 +17,52 @@ where
     I: FromBody,
     O: IntoBody,
 {
-    let req: Request<_> = req.try_into().unwrap();
+    run_http(req.try_into().unwrap(), response_out, handler)
+}
 
-    let (parts, body) = req.into_parts();
-    let body = match I::from_body(body) {
-        Ok(body) => body,
+/// Same as [`run`], for a request already converted to an `http::Request`
+pub fn run_http<I, O, F>(req: Request<IncomingBody>, response_out: ResponseOutparam, handler: F)
+where
+    F: FnOnce(Request<I>) -> Result<Response<O>>,
+    I: FromBody,
+    O: IntoBody,
+{
+    let Some((req, response_out)) = parse_body(req, response_out) else {
+        return;
+    };
+
+    let res = match handler(req) {
+        Ok(res) => res,
         Err(err) => {
-            eprintln!("Errored during body parsing: {err}");
+            eprintln!("Errored during request handling: {err}");
 
-            let res = json_error_response(StatusCode::BAD_REQUEST, err);
+            let res = json_error_response(StatusCode::INTERNAL_SERVER_ERROR, err);
             response_out.send(res).expect("Failed to send response");
             return;
         }
     };
-    let req = Request::from_parts(parts, body);
+
+    let (mut parts, data) = res.into_parts();
+    data.extend_response_parts(&mut parts);
+    let body = data.into_body().unwrap();
+    let res = Response::from_parts(parts, body);
+
+    response_out.send(res).expect("Failed to send response");
+}
+
+/// Same as [`run_http`], with a response body written as it is produced
+///
+/// Errors happening once the body started can't change the response status,
+/// so the body is left unfinished for the client to notice.
+pub fn run_stream<I, O, F>(req: Request<IncomingBody>, response_out: ResponseOutparam, handler: F)
+where
+    F: FnOnce(Request<I>) -> Result<Response<O>>,
+    I: FromBody,
+    O: IntoBodyStream,
+{
+    let Some((req, response_out)) = parse_body(req, response_out) else {
+        return;
+    };
 
     let res = match handler(req) {
         Ok(res) => res,
@@ -45,10 +77,39 @@ where
 
     let (mut parts, data) = res.into_parts();
     data.extend_response_parts(&mut parts);
-    let body = data.into_body().unwrap();
-    let res = Response::from_parts(parts, body);
+    let writer = response_out.start(parts).expect("Failed to send response");
 
-    response_out.send(res).expect("Failed to send response");
+    match data.write_body(&mut |chunk| writer.write(chunk)) {
+        Ok(()) => writer.finish().expect("Failed to send response"),
+        Err(err) => eprintln!("Errored during response streaming: {err}"),
+    }
+}
+
+/// Whether the request body has the content type, ignoring its parameters
+pub fn has_content_type<B>(req: &Request<B>, content_type: &str) -> bool {
+    req.headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case(content_type))
+}
+
+/// Parse the request body, answering with a bad request error on failure
+fn parse_body<I: FromBody>(
+    req: Request<IncomingBody>,
+    response_out: ResponseOutparam,
+) -> Option<(Request<I>, ResponseOutparam)> {
+    let (parts, body) = req.into_parts();
+    match I::from_body(body) {
+        Ok(body) => Some((Request::from_parts(parts, body), response_out)),
+        Err(err) => {
+            eprintln!("Errored during body parsing: {err}");
+
+            let res = json_error_response(StatusCode::BAD_REQUEST, err);
+            response_out.send(res).expect("Failed to send response");
+            None
+        }
+    }
 }
 
 fn json_error_response(status_code: StatusCode, err: anyhow::Error) -> Response<Bytes> {
@@ -3,7 +3,8 @@ use std::collections::HashMap;
 use anyhow::Result;
 
 use bindings::wasi::http::types::{IncomingRequest, ResponseOutparam};
-use helpers::body::Json;
+use classifier::Document;
+use helpers::body::{Html, Json, NdJson, NdJsonStream, Rfc822};
 
 mod bindings {
     wit_bindgen::generate!({
@@ -22,13 +23,144 @@ bindings::export!(Component);
 
 impl bindings::exports::wasi::http::incoming_handler::Guest for Component {
     fn handle(req: IncomingRequest, response_out: ResponseOutparam) {
-        helpers::run(req, response_out, handle);
+        let req = req.try_into().unwrap();
+        if helpers::has_content_type(&req, helpers::body::NDJSON_CONTENT_TYPE) {
+            helpers::run_stream(req, response_out, handle_stream);
+        } else if helpers::has_content_type(&req, helpers::body::RFC822_CONTENT_TYPE) {
+            helpers::run_http(req, response_out, handle_message);
+        } else if helpers::has_content_type(&req, helpers::body::HTML_CONTENT_TYPE) {
+            helpers::run_http(req, response_out, handle_html);
+        } else {
+            helpers::run_http(req, response_out, dispatch);
+        }
     }
 }
 
+/// Default maximum number of items in a batch request
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
 #[derive(Debug, Clone, serde::Deserialize)]
 struct Input {
-    input: String,
+    input: Message,
+    #[serde(default)]
+    explain: bool,
+}
+
+/// Input to classify, either a plain text or an email split into its fields
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum Message {
+    Text(String),
+    Email(classifier::email::Email),
+    /// HTML document, from an HTML request body or the `html` setting
+    #[serde(skip)]
+    Html(String),
+}
+
+impl Message {
+    /// Text echoed back in the output, the body of emails
+    fn text(&self) -> &str {
+        match self {
+            Self::Text(text) | Self::Html(text) => text,
+            Self::Email(email) => &email.body,
+        }
+    }
+
+    /// Same input with its text, or email body, read as HTML
+    fn to_html(&self) -> Self {
+        match self {
+            Self::Text(text) | Self::Html(text) => Self::Html(text.clone()),
+            Self::Email(email) => Self::Email(classifier::email::Email {
+                html: true,
+                ..email.clone()
+            }),
+        }
+    }
+
+    /// Parse the HTML of the message, once for every use of it
+    fn parse(&self) -> ParsedMessage<'_> {
+        match self {
+            Self::Text(text) => ParsedMessage::Text(text),
+            Self::Email(email) => ParsedMessage::Email(email.content()),
+            Self::Html(html) => ParsedMessage::Html(classifier::html::HtmlContent::parse(html)),
+        }
+    }
+}
+
+/// Message with its HTML parsed, read several times while classifying it
+enum ParsedMessage<'a> {
+    Text(&'a str),
+    Email(classifier::email::EmailContent<'a>),
+    Html(classifier::html::HtmlContent),
+}
+
+impl classifier::Document for ParsedMessage<'_> {
+    fn tokenize(&self, tokenizer: &classifier::TokenizerConfig) -> Vec<String> {
+        match self {
+            Self::Text(text) => text.tokenize(tokenizer),
+            Self::Email(email) => email.tokenize(tokenizer),
+            Self::Html(html) => html.tokenize(tokenizer),
+        }
+    }
+
+    fn tokenize_with_offsets(
+        &self,
+        tokenizer: &classifier::TokenizerConfig,
+    ) -> Vec<classifier::tokenizer::Token> {
+        match self {
+            Self::Text(text) => text.tokenize_with_offsets(tokenizer),
+            Self::Email(email) => email.tokenize_with_offsets(tokenizer),
+            Self::Html(html) => html.tokenize_with_offsets(tokenizer),
+        }
+    }
+
+    fn detect_language(&self, tokenizer: &classifier::TokenizerConfig) -> Option<&'static str> {
+        match self {
+            Self::Text(text) => text.detect_language(tokenizer),
+            Self::Email(email) => email.detect_language(tokenizer),
+            Self::Html(html) => html.detect_language(tokenizer),
+        }
+    }
+
+    fn urls(&self) -> Vec<classifier::url::Url> {
+        match self {
+            Self::Text(text) => text.urls(),
+            Self::Email(email) => email.urls(),
+            Self::Html(html) => html.urls(),
+        }
+    }
+
+    fn visible_text(&self) -> std::borrow::Cow<'_, str> {
+        match self {
+            Self::Text(text) => text.visible_text(),
+            Self::Email(email) => email.visible_text(),
+            Self::Html(html) => html.visible_text(),
+        }
+    }
+}
+
+/// Request body, either a single input or an array of inputs
+///
+/// Batch items are kept as raw JSON so that an invalid item only fails itself.
+#[derive(Debug, Clone)]
+enum Payload {
+    Batch(Vec<serde_json::Value>),
+    Single(Input),
+}
+
+/// Arrays are batches, anything else is a single input
+///
+/// Unlike an untagged enum, single inputs fail with the error naming their
+/// invalid field.
+impl<'de> serde::Deserialize<'de> for Payload {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Array(items) => Ok(Self::Batch(items)),
+            value => serde_json::from_value(value)
+                .map(Self::Single)
+                .map_err(serde::de::Error::custom),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -38,33 +170,240 @@ struct Output {
     ham_probability: f64,
     is_spam: bool,
     confidence: f64,
+    label: String,
+    labels: Vec<classifier::multiclass::LabelProbability>,
+    /// Language of the sub-model used, `None` when the global model was used
+    language: Option<String>,
+    verdict: classifier::decision::Verdict,
+    /// URLs found in the input, with their domain-level features
+    urls: Vec<classifier::url::Url>,
+    /// Domain list entries matched by the URLs
+    matched_lists: Vec<classifier::domains::ListMatch>,
+    /// Operator rules matched by the input, in configuration order
+    rule_hits: Vec<classifier::rules::RuleHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_costs: Option<classifier::decision::ExpectedCosts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interesting_tokens: Option<Vec<classifier::graham::InterestingToken>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<classifier::Explanation>,
+}
+
+/// Result of a batch item, either its output or the error it failed with
+#[derive(Debug, Clone, serde::Serialize)]
+struct BatchOutput {
+    /// Id supplied by the client, echoed back as is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    output: Option<Output>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Batch(Vec<BatchOutput>),
+    Single(Box<Output>),
+}
+
+fn dispatch(req: http::Request<Json<Payload>>) -> Result<http::Response<Json<Reply>>> {
+    let (parts, Json(payload)) = req.into_parts();
+
+    let res = match payload {
+        Payload::Single(input) => handle(http::Request::from_parts(parts, Json(input)))?
+            .map(|Json(output)| Reply::Single(Box::new(output))),
+        Payload::Batch(items) => handle_batch(http::Request::from_parts(parts, Json(items)))?
+            .map(|Json(outputs)| Reply::Batch(outputs)),
+    };
+
+    Ok(res.map(Json))
 }
 
 fn handle(req: http::Request<Json<Input>>) -> Result<http::Response<Json<Output>>> {
-    let Json(Input { ref input }) = req.body();
+    let Json(input) = req.body();
 
     let settings = Settings::from_req(&req)?;
-    let mut classifier = classifier::NaiveBayesClassifier::new();
-    classifier.set_spam_threshold(settings.spam_threshold);
-    classifier.set_alpha(settings.laplace_smoothing_factor);
-    let result = classifier.classify_detailed(input);
+    let router = settings.router();
 
     http::Response::builder()
         .status(200)
-        .body(Json(Output {
-            text: input.clone(),
-            spam_probability: result.spam_probability,
-            ham_probability: result.ham_probability,
-            is_spam: result.is_spam,
-            confidence: result.confidence,
-        }))
+        .body(Json(classify(&router, &settings, input)))
+        .map_err(Into::into)
+}
+
+/// Classify a raw email, as sent by mail gateways
+fn handle_message(
+    req: http::Request<Rfc822<classifier::email::Email>>,
+) -> Result<http::Response<Json<Output>>> {
+    let settings = Settings::from_req(&req)?;
+    let Rfc822(email) = req.into_body();
+    let input = Input {
+        input: Message::Email(email),
+        explain: false,
+    };
+
+    http::Response::builder()
+        .status(200)
+        .body(Json(classify(&settings.router(), &settings, &input)))
+        .map_err(Into::into)
+}
+
+/// Classify an HTML document, such as a form submission sent as is
+fn handle_html(req: http::Request<Html<String>>) -> Result<http::Response<Json<Output>>> {
+    let settings = Settings::from_req(&req)?;
+    let Html(html) = req.into_body();
+    let input = Input {
+        input: Message::Html(html),
+        explain: false,
+    };
+
+    http::Response::builder()
+        .status(200)
+        .body(Json(classify(&settings.router(), &settings, &input)))
         .map_err(Into::into)
 }
 
+fn handle_batch(
+    req: http::Request<Json<Vec<serde_json::Value>>>,
+) -> Result<http::Response<Json<Vec<BatchOutput>>>> {
+    let settings = Settings::from_req(&req)?;
+    let Json(items) = req.into_body();
+    if items.len() > settings.max_batch_size {
+        anyhow::bail!(
+            "Batch of {} items exceeds the maximum batch size of {}",
+            items.len(),
+            settings.max_batch_size
+        );
+    }
+
+    // The classifiers are configured once for the whole batch
+    let router = settings.router();
+    let outputs = items
+        .into_iter()
+        .map(|item| classify_item(&router, &settings, Ok(item)))
+        .collect();
+
+    http::Response::builder()
+        .status(200)
+        .body(Json(outputs))
+        .map_err(Into::into)
+}
+
+/// Classify NDJSON items as they are read, streaming back one result per line
+///
+/// Unlike batches, streams aren't limited in size.
+fn handle_stream(
+    req: http::Request<NdJson<serde_json::Value>>,
+) -> Result<http::Response<NdJsonStream<impl Iterator<Item = BatchOutput>>>> {
+    let settings = Settings::from_req(&req)?;
+    let router = settings.router();
+
+    let outputs = req
+        .into_body()
+        .map(move |item| classify_item(&router, &settings, item));
+
+    http::Response::builder()
+        .status(200)
+        .body(NdJsonStream(outputs))
+        .map_err(Into::into)
+}
+
+/// Classify a batch item, turning a failure into an error output
+fn classify_item(
+    router: &classifier::language::LanguageRouter,
+    settings: &Settings,
+    item: Result<serde_json::Value>,
+) -> BatchOutput {
+    let (id, input) = match item {
+        Ok(item) => (
+            item.get("id").cloned(),
+            serde_json::from_value::<Input>(item).map_err(Into::into),
+        ),
+        Err(err) => (None, Err(err)),
+    };
+
+    match input {
+        Ok(input) => BatchOutput {
+            id,
+            output: Some(classify(router, settings, &input)),
+            error: None,
+        },
+        Err(err) => BatchOutput {
+            id,
+            output: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+fn classify(
+    router: &classifier::language::LanguageRouter,
+    settings: &Settings,
+    Input { input, explain }: &Input,
+) -> Output {
+    let html;
+    let input = if settings.html {
+        html = input.to_html();
+        &html
+    } else {
+        input
+    };
+
+    let document = &input.parse();
+
+    let (language, classifier) = router.route(document);
+    let result = if *explain {
+        classifier.classify_explained(document, settings.explain_top_n)
+    } else {
+        classifier.classify_detailed(document)
+    };
+
+    let urls = document.urls();
+    let matched_lists = classifier::domains::DomainLists::shared().matches(&urls);
+    let result = classifier.apply_domain_lists(result, &matched_lists, settings.domain_lists);
+
+    let rule_hits = settings.rules.evaluate(&document.visible_text());
+    let result = classifier.apply_rules(result, &rule_hits);
+
+    Output {
+        text: input.text().to_string(),
+        spam_probability: result.spam_probability,
+        ham_probability: result.ham_probability,
+        is_spam: result.is_spam,
+        confidence: result.confidence,
+        label: result.label,
+        labels: result.labels,
+        language: language.map(str::to_string),
+        verdict: result.verdict,
+        urls,
+        matched_lists,
+        rule_hits,
+        expected_costs: result.expected_costs,
+        interesting_tokens: result.interesting_tokens,
+        explanation: result.explanation,
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Settings {
     pub spam_threshold: f64,
     pub laplace_smoothing_factor: f64,
+    pub explain_top_n: usize,
+    pub mode: classifier::ScoringMode,
+    pub chi_squared: classifier::chi_squared::ChiSquaredConfig,
+    pub interesting_tokens: usize,
+    pub decision: classifier::decision::DecisionThresholds,
+    pub costs: Option<classifier::decision::MisclassificationCosts>,
+    pub max_batch_size: usize,
+    /// Whether text inputs and email bodies are HTML
+    pub html: bool,
+    /// How domain list matches affect the result
+    pub domain_lists: classifier::domains::ListAction,
+    /// Operator rules, compiled once per instance
+    #[serde(skip)]
+    pub rules: std::sync::Arc<classifier::rules::RuleSet>,
 }
 
 impl Settings {
@@ -85,12 +424,134 @@ impl Settings {
             .and_then(|s| s.parse::<f64>().ok())
             .unwrap_or(classifier::DEFAULT_ALPHA);
 
+        let explain_top_n = data
+            .get("explain_top_n")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(classifier::DEFAULT_EXPLAIN_TOP_N);
+
+        let mode = data
+            .get("mode")
+            .and_then(|s| s.parse::<classifier::ScoringMode>().ok())
+            .unwrap_or_default();
+
+        let defaults = classifier::chi_squared::ChiSquaredConfig::default();
+        let chi_squared = classifier::chi_squared::ChiSquaredConfig::new(
+            data.get("robinson_strength")
+                .and_then(|s| s.parse::<f64>().ok())
+                .unwrap_or(defaults.strength),
+            data.get("robinson_prior")
+                .and_then(|s| s.parse::<f64>().ok())
+                .unwrap_or(defaults.prior),
+        )?;
+
+        let interesting_tokens = data
+            .get("interesting_tokens")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(classifier::graham::DEFAULT_INTERESTING_TOKENS);
+
+        let false_positive_cost = data
+            .get("false_positive_cost")
+            .and_then(|s| s.parse::<f64>().ok());
+
+        let false_negative_cost = data
+            .get("false_negative_cost")
+            .and_then(|s| s.parse::<f64>().ok());
+
+        // Costs are only used when at least one of them is set, the other one defaulting to 1
+        let costs = match (false_positive_cost, false_negative_cost) {
+            (None, None) => None,
+            (false_positive, false_negative) => {
+                Some(classifier::decision::MisclassificationCosts::new(
+                    false_positive.unwrap_or(1.0),
+                    false_negative.unwrap_or(1.0),
+                )?)
+            }
+        };
+        let decision_threshold = costs.map_or(spam_threshold, |costs| costs.threshold());
+
+        // Without a grey zone, verdicts follow `is_spam`, except for the
+        // chi-squared mode which comes with its own cutoffs
+        let (default_ham_below, default_spam_above) = match mode {
+            classifier::ScoringMode::ChiSquared => (
+                classifier::chi_squared::HAM_CUTOFF,
+                classifier::chi_squared::SPAM_CUTOFF,
+            ),
+            _ => (decision_threshold, decision_threshold),
+        };
+
+        let ham_below = data
+            .get("ham_below")
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(default_ham_below);
+
+        let spam_above = data
+            .get("spam_above")
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(default_spam_above);
+
+        let decision = classifier::decision::DecisionThresholds::new(ham_below, spam_above)?;
+
+        let max_batch_size = data
+            .get("max_batch_size")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+
+        let html = data
+            .get("html")
+            .and_then(|s| s.parse::<bool>().ok())
+            .unwrap_or(false);
+
+        let domain_list_boost = data
+            .get("domain_list_boost")
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|boost| boost.is_finite() && *boost >= 0.0)
+            .unwrap_or(classifier::domains::DEFAULT_LIST_BOOST);
+
+        let domain_lists = match data.get("domain_list_action").map(String::as_str) {
+            Some("boost") => classifier::domains::ListAction::Boost(domain_list_boost),
+            Some("off") => classifier::domains::ListAction::Off,
+            _ => classifier::domains::ListAction::Override,
+        };
+
+        // Invalid rules fail the request rather than being silently ignored
+        let rules = match data.get("rules") {
+            Some(source) if !source.trim().is_empty() => {
+                classifier::rules::RuleSet::shared(source)?
+            }
+            _ => Default::default(),
+        };
+
         Ok(Self {
             spam_threshold,
             laplace_smoothing_factor,
+            explain_top_n,
+            mode,
+            chi_squared,
+            interesting_tokens,
+            decision,
+            costs,
+            max_batch_size,
+            html,
+            domain_lists,
+            rules,
         })
     }
 
+    /// Get the shared language router with these settings applied to every classifier
+    pub fn router(&self) -> classifier::language::LanguageRouter<'static> {
+        let mut router = classifier::language::LanguageRouter::shared().clone();
+        router.configure(|classifier| {
+            classifier.set_spam_threshold(self.spam_threshold);
+            classifier.set_alpha(self.laplace_smoothing_factor);
+            classifier.set_mode(self.mode);
+            classifier.set_chi_squared(self.chi_squared);
+            classifier.set_interesting_tokens(self.interesting_tokens);
+            classifier.set_decision_thresholds(self.decision);
+            classifier.set_costs(self.costs);
+        });
+        router
+    }
+
     pub fn from_req<B>(req: &http::Request<B>) -> Result<Self> {
         Self::new(req.headers())
     }
@@ -104,7 +565,8 @@ mod tests {
     fn test_handle_function() {
         // Create test input
         let input = Input {
-            input: "Hello, this is a test message".to_string(),
+            input: Message::Text("Hello, this is a test message".to_string()),
+            explain: false,
         };
 
         let req = http::Request::builder()
@@ -135,7 +597,8 @@ mod tests {
     #[test]
     fn test_handle_spam_input() {
         let input = Input {
-            input: "FREE MONEY! Click here to win $1000000!".to_string(),
+            input: Message::Text("FREE MONEY! Click here to win $1000000!".to_string()),
+            explain: false,
         };
 
         let req = http::Request::builder()
@@ -160,7 +623,8 @@ mod tests {
     #[test]
     fn test_handle_ham_input() {
         let input = Input {
-            input: "Good morning! How are you today?".to_string(),
+            input: Message::Text("Good morning! How are you today?".to_string()),
+            explain: false,
         };
 
         let req = http::Request::builder()
@@ -184,7 +648,8 @@ mod tests {
     #[test]
     fn test_handle_empty_input() {
         let input = Input {
-            input: "".to_string(),
+            input: Message::Text("".to_string()),
+            explain: false,
         };
 
         let req = http::Request::builder()
@@ -208,7 +673,8 @@ mod tests {
     #[test]
     fn test_output_structure() {
         let input = Input {
-            input: "Test message for structure validation".to_string(),
+            input: Message::Text("Test message for structure validation".to_string()),
+            explain: false,
         };
 
         let req = http::Request::builder()
@@ -256,5 +722,597 @@ mod tests {
             output.ham_probability
         };
         assert!((output.confidence - expected_confidence).abs() < 0.001);
+        assert!(output.explanation.is_none());
+        assert_eq!(
+            output.verdict == classifier::decision::Verdict::Spam,
+            output.is_spam
+        );
+        assert!(output.interesting_tokens.is_none());
+        assert!(output.expected_costs.is_none());
+
+        // Test label probabilities
+        assert!(!output.labels.is_empty());
+        assert_eq!(output.label == "ham", !output.is_spam);
+        let label_sum: f64 = output.labels.iter().map(|label| label.probability).sum();
+        assert!((label_sum - 1.0).abs() < 0.001);
+
+        // Test the reported language has a sub-model
+        if let Some(language) = &output.language {
+            assert!(classifier::language::LanguageRouter::shared()
+                .languages()
+                .any(|known| known == language));
+        }
+    }
+
+    #[test]
+    fn test_handle_explain() {
+        let input = Input {
+            input: Message::Text("FREE MONEY! Click here to win $1000000!".to_string()),
+            explain: true,
+        };
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("x-edgee-component-settings", r#"{"explain_top_n": "1"}"#)
+            .body(Json(input))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        let explanation = output.explanation.as_ref().unwrap();
+        assert!(!explanation.tokens.is_empty());
+        assert!(explanation.top_spam.len() <= 1);
+        assert!(explanation.top_ham.len() <= 1);
+
+        let json = serde_json::to_value(output).unwrap();
+        assert!(json["explanation"]["tokens"][0]["counter"]["spam"].is_u64());
+    }
+
+    #[test]
+    fn test_settings_mode() {
+        let mut headers = http::header::HeaderMap::new();
+        headers.insert(
+            "x-edgee-component-settings",
+            r#"{"mode": "complement"}"#.parse().unwrap(),
+        );
+        assert_eq!(
+            Settings::new(&headers).unwrap().mode,
+            classifier::ScoringMode::Complement
+        );
+
+        // Unknown modes fall back to multinomial
+        headers.insert(
+            "x-edgee-component-settings",
+            r#"{"mode": "gaussian"}"#.parse().unwrap(),
+        );
+        assert_eq!(
+            Settings::new(&headers).unwrap().mode,
+            classifier::ScoringMode::Multinomial
+        );
+    }
+
+    #[test]
+    fn test_settings_robinson() {
+        let mut headers = http::header::HeaderMap::new();
+        headers.insert(
+            "x-edgee-component-settings",
+            r#"{"robinson_strength": "1.0", "robinson_prior": "0.4"}"#
+                .parse()
+                .unwrap(),
+        );
+        let chi_squared = Settings::new(&headers).unwrap().chi_squared;
+        assert_eq!((chi_squared.strength, chi_squared.prior), (1.0, 0.4));
+
+        // Out of range values would turn Robinson probabilities into NaN
+        for settings in [
+            r#"{"robinson_strength": "-0.5"}"#,
+            r#"{"robinson_strength": "0"}"#,
+            r#"{"robinson_prior": "1.5"}"#,
+        ] {
+            headers.insert("x-edgee-component-settings", settings.parse().unwrap());
+            assert!(Settings::new(&headers).is_err());
+        }
+    }
+
+    #[test]
+    fn test_handle_chi_squared() {
+        let input = Input {
+            input: Message::Text("FREE MONEY! Click here to win $1000000!".to_string()),
+            explain: false,
+        };
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"mode": "chi_squared", "ham_below": "0.3"}"#,
+            )
+            .body(Json(input))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        // The spam cutoff defaults to the chi-squared one
+        let expected = if output.spam_probability < 0.3 {
+            classifier::decision::Verdict::Ham
+        } else if output.spam_probability >= classifier::chi_squared::SPAM_CUTOFF {
+            classifier::decision::Verdict::Spam
+        } else {
+            classifier::decision::Verdict::NeedsReview
+        };
+        assert_eq!(output.verdict, expected);
+    }
+
+    #[test]
+    fn test_handle_graham() {
+        let input = Input {
+            input: Message::Text("FREE MONEY! Click here to win $1000000!".to_string()),
+            explain: false,
+        };
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"mode": "graham", "interesting_tokens": "2"}"#,
+            )
+            .body(Json(input))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        let interesting_tokens = output.interesting_tokens.as_ref().unwrap();
+        assert_eq!(interesting_tokens.len(), 2);
+        assert!(output.spam_probability.is_finite());
+    }
+
+    #[test]
+    fn test_handle_needs_review() {
+        let input = Input {
+            input: Message::Text("Hello, this is a test message".to_string()),
+            explain: false,
+        };
+
+        // The whole range is a grey zone
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"ham_below": "0.0", "spam_above": "1.0"}"#,
+            )
+            .body(Json(input.clone()))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+        assert_eq!(output.verdict, classifier::decision::Verdict::NeedsReview);
+
+        let json = serde_json::to_value(output).unwrap();
+        assert_eq!(json["verdict"], "needs_review");
+
+        // Inverted thresholds are rejected
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"ham_below": "0.9", "spam_above": "0.2"}"#,
+            )
+            .body(Json(input))
+            .unwrap();
+
+        assert!(handle(req).is_err());
+    }
+
+    #[test]
+    fn test_handle_costs() {
+        let input = Input {
+            input: Message::Text("FREE MONEY! Click here to win $1000000!".to_string()),
+            explain: false,
+        };
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"false_positive_cost": "20", "false_negative_cost": "1"}"#,
+            )
+            .body(Json(input.clone()))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        let expected_costs = output.expected_costs.unwrap();
+        assert!((expected_costs.spam - 20.0 * output.ham_probability).abs() < 1e-9);
+        assert!((expected_costs.ham - output.spam_probability).abs() < 1e-9);
+        assert_eq!(output.is_spam, expected_costs.spam <= expected_costs.ham);
+        assert_eq!(
+            output.verdict == classifier::decision::Verdict::Spam,
+            output.is_spam
+        );
+
+        // Negative costs are rejected
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"false_positive_cost": "-1"}"#,
+            )
+            .body(Json(input))
+            .unwrap();
+
+        assert!(handle(req).is_err());
+    }
+
+    #[test]
+    fn test_handle_batch() {
+        let payload: Payload = serde_json::from_value(serde_json::json!([
+            { "id": "a", "input": "FREE MONEY! Click here to win $1000000!" },
+            { "input": "Good morning! How are you today?" },
+            { "id": 3, "text": "missing input" },
+        ]))
+        .unwrap();
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("x-edgee-component-settings", "{}")
+            .body(Json(payload))
+            .unwrap();
+
+        let response = dispatch(req).unwrap();
+        let Json(Reply::Batch(outputs)) = response.body() else {
+            panic!("Expected a batch reply");
+        };
+
+        // Results come back in order, invalid items failing on their own
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].id, Some(serde_json::json!("a")));
+        let output = outputs[0].output.as_ref().unwrap();
+        assert_eq!(output.text, "FREE MONEY! Click here to win $1000000!");
+        assert_eq!(outputs[1].id, None);
+        assert_eq!(
+            outputs[1].output.as_ref().unwrap().text,
+            "Good morning! How are you today?"
+        );
+        assert_eq!(outputs[2].id, Some(serde_json::json!(3)));
+        assert!(outputs[2].output.is_none());
+        assert!(outputs[2].error.is_some());
+
+        let json = serde_json::to_value(outputs).unwrap();
+        assert_eq!(json[0]["id"], "a");
+        assert!(json[0]["spam_probability"].is_f64());
+        assert!(json[2]["error"].is_string());
+    }
+
+    #[test]
+    fn test_handle_batch_size() {
+        let items = vec![serde_json::json!({ "input": "hello" }); 3];
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("x-edgee-component-settings", r#"{"max_batch_size": "2"}"#)
+            .body(Json(items))
+            .unwrap();
+
+        assert!(handle_batch(req).is_err());
+    }
+
+    #[test]
+    fn test_single_payload() {
+        let payload: Payload =
+            serde_json::from_value(serde_json::json!({ "input": "hello", "explain": true }))
+                .unwrap();
+        assert!(matches!(
+            payload,
+            Payload::Single(Input { explain: true, .. })
+        ));
+
+        // Errors name the invalid field
+        let error = serde_json::from_value::<Payload>(serde_json::json!({ "text": "hello" }))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("missing field `input`"), "{error}");
+
+        let error = serde_json::from_value::<Payload>(
+            serde_json::json!({ "input": "hello", "explain": "yes" }),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("invalid type: string \"yes\""), "{error}");
+    }
+
+    #[test]
+    fn test_handle_stream() {
+        use helpers::body::{FromBody, IntoBodyStream};
+
+        let body = concat!(
+            r#"{"id": 1, "input": "FREE MONEY! Click here to win $1000000!"}"#,
+            "\n\n",
+            "not json\n",
+            r#"{"id": 3, "input": "Good morning! How are you today?"}"#,
+        );
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "application/x-ndjson")
+            .header("x-edgee-component-settings", "{}")
+            .body(NdJson::from_data(body.into()).unwrap())
+            .unwrap();
+        assert!(helpers::has_content_type(
+            &req,
+            helpers::body::NDJSON_CONTENT_TYPE
+        ));
+
+        let response = handle_stream(req).unwrap();
+        let mut written = Vec::new();
+        response
+            .into_body()
+            .write_body(&mut |chunk| {
+                written.extend_from_slice(chunk);
+                Ok(())
+            })
+            .unwrap();
+
+        // One line per item, blank lines skipped and invalid ones reported
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&written)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["id"], 1);
+        assert!(lines[0]["spam_probability"].is_f64());
+        assert!(lines[1]["error"].is_string());
+        assert_eq!(lines[2]["id"], 3);
+        assert_eq!(lines[2]["text"], "Good morning! How are you today?");
+    }
+
+    #[test]
+    fn test_stream_line_too_long() {
+        use helpers::body::{FromBody, MAX_NDJSON_LINE_LENGTH};
+
+        let long = format!(r#"{{"input": "{}"}}"#, "a".repeat(MAX_NDJSON_LINE_LENGTH));
+        let body = format!("{long}\n{{\"input\": \"hello\"}}\n{long}");
+
+        // Only the long lines fail
+        let items: Vec<_> = NdJson::<Input>::from_data(body.into()).unwrap().collect();
+        assert_eq!(items.len(), 3);
+        assert!(items[0]
+            .as_ref()
+            .is_err_and(|err| err.to_string().contains("Line longer")));
+        assert_eq!(items[1].as_ref().unwrap().input.text(), "hello");
+        assert!(items[2].is_err());
+    }
+
+    #[test]
+    fn test_handle_email() {
+        let input: Input = serde_json::from_value(serde_json::json!({
+            "input": {
+                "subject": "FREE MONEY!",
+                "body": "Click here to win $1000000!",
+                "from": "winner@example.com",
+                "headers": { "X-Mailer": "Bulk" },
+            },
+            "explain": true,
+        }))
+        .unwrap();
+
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("x-edgee-component-settings", "{}")
+            .body(Json(input))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        assert_eq!(output.text, "Click here to win $1000000!");
+        assert!(output.spam_probability.is_finite());
+
+        // Tokens of other fields than the body are prefixed
+        let explanation = output.explanation.as_ref().unwrap();
+        assert!(explanation
+            .tokens
+            .iter()
+            .any(|token| token.token.starts_with(classifier::email::SUBJECT_PREFIX)));
+
+        // Other objects aren't taken for empty emails
+        for input in [serde_json::json!({}), serde_json::json!({ "txt": "hello" })] {
+            let json = serde_json::json!({ "input": input });
+            assert!(serde_json::from_value::<Input>(json).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_handle_message() {
+        use helpers::body::FromBody;
+
+        let message = concat!(
+            "From: winner@example.com\r\n",
+            "Subject: =?utf-8?Q?FREE_MONEY!?=\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "Q2xpY2sgaGVyZSB0byB3aW4gJDEwMDAwMDAh\r\n",
+        );
+
+        let req: http::Request<Rfc822<classifier::email::Email>> = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "message/rfc822")
+            .header("x-edgee-component-settings", "{}")
+            .body(Rfc822::from_data(message.into()).unwrap())
+            .unwrap();
+        assert_eq!(req.body().0.subject, "FREE MONEY!");
+
+        let response = handle_message(req).unwrap();
+        let Json(output) = response.body();
+        assert_eq!(output.text, "Click here to win $1000000!");
+        assert!(output.spam_probability.is_finite());
+    }
+
+    #[test]
+    fn test_html_setting() {
+        let html = r#"<p>Hello <b>friend</b></p><div style="display:none">cheap pills</div>"#;
+        let hidden_text = classifier::html::HtmlFeature::HiddenText.token();
+
+        for (settings, is_html) in [("{}", false), (r#"{"html": "true"}"#, true)] {
+            let input = Input {
+                input: Message::Text(html.to_string()),
+                explain: true,
+            };
+            let req = http::Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("x-edgee-component-settings", settings)
+                .body(Json(input))
+                .unwrap();
+
+            let response = handle(req).unwrap();
+            let Json(output) = response.body();
+            let tokens = &output.explanation.as_ref().unwrap().tokens;
+
+            // Tags and hidden text only reach the model without the setting
+            assert_eq!(tokens.iter().any(|token| token.token == "div"), !is_html);
+            assert_eq!(
+                tokens.iter().any(|token| token.token == hidden_text),
+                is_html
+            );
+        }
+    }
+
+    #[test]
+    fn test_handle_html() {
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "text/html; charset=utf-8")
+            .header("x-edgee-component-settings", "{}")
+            .body(Html(
+                r#"<a href="http://login.example.ru">www.paypal.com</a>"#.to_string(),
+            ))
+            .unwrap();
+
+        let response = handle_html(req).unwrap();
+        let Json(output) = response.body();
+        assert!(output.text.starts_with("<a href"));
+        assert!(output.spam_probability.is_finite());
+    }
+
+    #[test]
+    fn test_handle_urls() {
+        let input = Input {
+            input: Message::Text("Claim your prize at http://198.51.100.7/claim now".to_string()),
+            explain: true,
+        };
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("x-edgee-component-settings", "{}")
+            .body(Json(input))
+            .unwrap();
+
+        let response = handle(req).unwrap();
+        let Json(output) = response.body();
+
+        assert_eq!(output.urls.len(), 1);
+        assert_eq!(output.urls[0].url, "http://198.51.100.7/claim");
+        assert!(output.urls[0].ip);
+
+        let explanation = output.explanation.as_ref().unwrap();
+        let url = explanation
+            .tokens
+            .iter()
+            .find(|token| token.token == classifier::url::IP_HOST_FEATURE)
+            .unwrap();
+        assert_eq!((url.start, url.end), (20, 45));
+    }
+
+    #[test]
+    fn test_domain_list_settings() {
+        use classifier::domains::{ListAction, DEFAULT_LIST_BOOST};
+
+        let settings = |value: &str| {
+            let mut headers = http::header::HeaderMap::new();
+            headers.insert("x-edgee-component-settings", value.parse().unwrap());
+            Settings::new(&headers).unwrap().domain_lists
+        };
+
+        assert_eq!(settings("{}"), ListAction::Override);
+        assert_eq!(
+            settings(r#"{"domain_list_action": "off"}"#),
+            ListAction::Off
+        );
+        assert_eq!(
+            settings(r#"{"domain_list_action": "boost"}"#),
+            ListAction::Boost(DEFAULT_LIST_BOOST)
+        );
+        assert_eq!(
+            settings(r#"{"domain_list_action": "boost", "domain_list_boost": "2.5"}"#),
+            ListAction::Boost(2.5)
+        );
+    }
+
+    #[test]
+    fn test_handle_rules() {
+        let rules = serde_json::json!([
+            {"name": "prize", "regex": r"win \$\d+", "score": 20.0},
+            {"name": "partner", "literal": "ACME Corp", "case_sensitive": true, "verdict": "ham"},
+        ]);
+        let settings = serde_json::json!({ "rules": rules.to_string() }).to_string();
+
+        let classify = |text: &str| {
+            let input = Input {
+                input: Message::Text(text.to_string()),
+                explain: false,
+            };
+            let req = http::Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("x-edgee-component-settings", &settings)
+                .body(Json(input))
+                .unwrap();
+            handle(req).unwrap().into_body().0
+        };
+
+        let output = classify("Hello, win $500 today");
+        assert_eq!(output.rule_hits.len(), 1);
+        assert_eq!(output.rule_hits[0].rule, "prize");
+        assert_eq!(output.rule_hits[0].matched, "win $500");
+        assert!(output.is_spam);
+
+        let output = classify("ACME Corp: win $500 today");
+        assert_eq!(output.rule_hits.len(), 2);
+        assert_eq!(output.verdict, classifier::decision::Verdict::Ham);
+        assert!(!output.is_spam);
+
+        // Invalid rules fail the request
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(
+                "x-edgee-component-settings",
+                r#"{"rules": "[{\"name\": \"x\"}]"}"#,
+            )
+            .body(Json(Input {
+                input: Message::Text("Hello".to_string()),
+                explain: false,
+            }))
+            .unwrap();
+        assert!(handle(req).is_err());
     }
 }